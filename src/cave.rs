@@ -0,0 +1,190 @@
+//! Contains the cave generator.
+//!
+//! Caves start as random noise, which is smoothed into rounded caverns by repeatedly
+//! turning each cell into a wall if most of its neighbours are walls (a cellular automaton).
+//! Only the largest cavern is kept, so every part of the cave can be reached.
+
+use crate::{
+    game::CellProperties,
+    grid::Grid,
+    map::Map,
+    util::{Rng, Vector2}
+};
+
+/// Texture id of the cave walls
+const ROCK_TEXTURE: i32 = 1;
+/// Texture id of the odd wall which stands out from the rock
+const ORE_TEXTURE: i32 = 2;
+/// Texture id of the floor of the exit cell
+const EXIT_TEXTURE: i32 = 3;
+
+/// How a cave should be generated.
+#[derive(Clone, Copy)]
+pub struct CaveOptions {
+    /// Size of the world along x, including the outer wall
+    pub width: usize,
+    /// Size of the world along y, including the outer wall
+    pub height: usize,
+    /// Chance of each cell starting as a wall, caves are more open below 0.45
+    pub fill: f32,
+    /// Number of smoothing passes, more passes give smoother walls
+    pub smoothing: usize,
+    pub seed: u64
+}
+
+impl Default for CaveOptions {
+    fn default() -> Self {
+        Self {
+            width: 48,
+            height: 48,
+            fill: 0.45,
+            smoothing: 5,
+            seed: 0
+        }
+    }
+}
+
+/// File names of the textures used by generated caves, texture id `n` is at index `n-1`.
+pub fn textures() -> Vec<String> {
+    ["LAVAROCKS.png", "PIPES.png", "CROSSCUBE.png"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// Generates a cave, with the spawn in a random part of it and the exit in the cell furthest away.
+pub fn generate(options: &CaveOptions) -> Map {
+    let width = options.width.max(5);
+    let height = options.height.max(5);
+    let mut rng = Rng::new(options.seed);
+
+    let mut walls = Grid::new(width, height, true);
+    for x in 1..width - 1 {
+        for y in 1..height - 1 {
+            walls[x][y] = rng.chance(options.fill);
+        }
+    }
+
+    for _ in 0..options.smoothing {
+        walls = smooth(&walls);
+    }
+
+    // Keep the largest cavern, filling in the rest
+    let mut largest = Vec::new();
+    let mut seen = Grid::new(width, height, false);
+    for x in 0..width {
+        for y in 0..height {
+            if walls[x][y] || seen[x][y] { continue }
+
+            let cavern = walls.flood_fill((x, y), |_, _, wall| !wall);
+            for &(cavern_x, cavern_y) in &cavern {
+                seen[cavern_x][cavern_y] = true;
+            }
+            if cavern.len() > largest.len() {
+                largest = cavern;
+            }
+        }
+    }
+
+    // The noise might not leave any floor at all, so a room is opened up in the middle
+    if largest.is_empty() {
+        for x in width / 2 - 1..=width / 2 {
+            for y in height / 2 - 1..=height / 2 {
+                walls[x][y] = false;
+            }
+        }
+        largest = walls.flood_fill((width / 2, height / 2), |_, _, wall| !wall);
+    }
+
+    let mut world = Grid::new(width, height, ROCK_TEXTURE);
+    for &(x, y) in &largest {
+        world[x][y] = 0;
+    }
+    for x in 0..width {
+        for y in 0..height {
+            if world[x][y] != 0 && rng.chance(0.05) {
+                world[x][y] = ORE_TEXTURE;
+            }
+        }
+    }
+
+    let spawn = largest[rng.range(largest.len())];
+    // The flood fill reaches the furthest cell last
+    let exit = *world.flood_fill(spawn, |_, _, cell| *cell == 0).last().unwrap_or(&spawn);
+
+    let mut map = Map::new(textures(), world, Vector2::new(spawn.0 as f32 + 0.5, spawn.1 as f32 + 0.5));
+
+    // Face the longest open view from the spawn
+    let open_cells = |(dx, dy): (isize, isize)| {
+        (1..).take_while(|&steps| {
+            let (x, y) = (spawn.0.wrapping_add_signed(dx * steps), spawn.1.wrapping_add_signed(dy * steps));
+            map.world.get(x, y) == Some(&0)
+        }).count()
+    };
+    if let Some((dx, dy)) = [(-1, 0), (1, 0), (0, -1), (0, 1)].into_iter().max_by_key(|&direction| open_cells(direction)) {
+        map.spawn_direction = Vector2::new(dx as f32, dy as f32);
+    }
+
+    map.metadata.name = format!("Cave {}x{} (seed {})", width, height, options.seed);
+    map.exit = Some(exit);
+    map.cell_properties[exit.0][exit.1] = CellProperties {
+        floor_texture: EXIT_TEXTURE,
+        ..CellProperties::default()
+    };
+
+    map
+}
+
+/// Runs one pass of the cellular automaton. A cell becomes a wall if at least five of the
+/// nine cells around and including it are walls, and the outer wall of the world stays solid.
+fn smooth(walls: &Grid<bool>) -> Grid<bool> {
+    let mut smoothed = walls.clone();
+
+    for x in 1..walls.width() - 1 {
+        for y in 1..walls.height() - 1 {
+            let wall_count = (x - 1..=x + 1)
+                .flat_map(|nx| (y - 1..=y + 1).map(move |ny| (nx, ny)))
+                .filter(|&(nx, ny)| walls[nx][ny])
+                .count();
+            smoothed[x][y] = wall_count >= 5;
+        }
+    }
+
+    smoothed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cave_is_walled_in_and_every_open_cell_can_be_reached() {
+        for (width, height) in [(2, 3), (5, 5), (16, 40), (48, 48), (80, 60)] {
+            for fill in [0.0, 0.45, 0.6, 1.0] {
+                for seed in 0..10 {
+                    let map = generate(&CaveOptions { width, height, fill, seed, ..CaveOptions::default() });
+                    let world = &map.world;
+                    let name = format!("{} fill {fill}", map.metadata.name);
+
+                    let spawn = (map.spawn_position.x as usize, map.spawn_position.y as usize);
+                    let mut reached = Grid::new(world.width(), world.height(), false);
+                    for (x, y) in world.flood_fill(spawn, |_, _, cell| *cell == 0) {
+                        reached[x][y] = true;
+                    }
+
+                    for x in 0..world.width() {
+                        for y in 0..world.height() {
+                            let solid = map.cell_types.get((world[x][y] - 1) as usize).is_some_and(|cell_type| cell_type.solid);
+                            if world.on_boundary(x, y) {
+                                assert!(solid, "{name}: boundary cell ({x}, {y}) isn't solid");
+                            }
+                            if world[x][y] == 0 {
+                                assert!(reached[x][y], "{name}: open cell ({x}, {y}) can't be reached");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,54 @@
+//! Contains the table of cell types which the values in the world refer to.
+
+/// A side of a wall, named by the direction it faces.
+///
+/// North faces towards -x and west faces towards -y,
+/// which is up and left on the minimap.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Face {
+    North,
+    South,
+    East,
+    West
+}
+
+impl Face {
+    /// Returns the face of a wall hit by a ray stepping through the world by `step_x` and `step_y`,
+    /// which entered the wall's cell across the x axis if `side_facing_x_axis`.
+    pub fn hit_by(side_facing_x_axis: bool, step_x: i32, step_y: i32) -> Face {
+        match (side_facing_x_axis, step_x > 0, step_y > 0) {
+            (true, true, _) => Face::North,
+            (true, false, _) => Face::South,
+            (false, _, true) => Face::West,
+            (false, _, false) => Face::East
+        }
+    }
+}
+
+/// What a non-zero cell in the world looks like and how it behaves.
+#[derive(Clone, PartialEq)]
+pub struct CellType {
+    /// Texture ids of the faces, in the order of `Face`
+    pub faces: [i32; 4],
+    /// The player can't walk through the cell
+    pub solid: bool,
+    /// Rays continue through the cell, so anything behind it can be seen
+    /// through the transparent parts of its textures
+    pub transparent: bool
+}
+
+impl CellType {
+    /// A solid, opaque cell with the same texture on every face.
+    pub fn uniform(texture: i32) -> CellType {
+        CellType {
+            faces: [texture; 4],
+            solid: true,
+            transparent: false
+        }
+    }
+
+    /// Returns the texture id of `face`.
+    pub fn texture(&self, face: Face) -> i32 {
+        self.faces[face as usize]
+    }
+}
\ No newline at end of file
@@ -0,0 +1,178 @@
+//! Contains the collision between the player and the walls of the world.
+//!
+//! The player is a circle which is pushed out of any solid cell it overlaps.
+//! Only the part of the movement going into a wall is removed, so the player slides
+//! along walls and rolls round corners instead of stopping dead.
+
+use crate::util::Vector2;
+
+/// Extra distance the circle is pushed out by, so rounding can't leave it touching the wall
+const SKIN: f32 = 1e-4;
+/// Number of times the overlaps are resolved each step, as pushing out of one cell
+/// can push the circle into another, such as in the corner of a room
+const RESOLVE_PASSES: usize = 3;
+
+/// Moves a circle of `radius` centred on `position` by `motion`, sliding along any solid cells in the way.
+/// `is_solid` is given the (x, y) coordinates of a cell, and should treat anything outside of the world as solid.
+///
+/// The movement is split into steps shorter than half the radius, so the circle can't pass through a wall
+/// however far it moves at once. A step which can't be resolved is dropped,
+/// so the centre of the circle never ends up inside a solid cell.
+pub fn move_circle(position: Vector2<f32>, radius: f32, motion: Vector2<f32>, is_solid: impl Fn(i32, i32) -> bool) -> Vector2<f32> {
+    let distance = (motion.x * motion.x + motion.y * motion.y).sqrt();
+    if distance == 0.0 || !distance.is_finite() { return position }
+
+    let max_step = (radius * 0.5).max(0.01);
+    let steps = (distance / max_step).ceil() as usize;
+    let step = Vector2::new(motion.x / steps as f32, motion.y / steps as f32);
+
+    let mut position = position;
+    for _ in 0..steps {
+        let mut moved = Vector2::new(position.x + step.x, position.y + step.y);
+
+        for _ in 0..RESOLVE_PASSES {
+            if !push_out(&mut moved, radius, &is_solid) { break }
+        }
+
+        if overlaps(moved, radius, &is_solid) {
+            // Wedged between walls, so this step is as far as the circle can go
+            break
+        }
+        position = moved;
+    }
+
+    position
+}
+
+/// Returns true if a circle of `radius` centred on `position` overlaps any solid cell.
+pub fn overlaps(position: Vector2<f32>, radius: f32, is_solid: impl Fn(i32, i32) -> bool) -> bool {
+    cells_under(position, radius).any(|(x, y)| {
+        is_solid(x, y) && squared_distance_to_cell(position, x, y) < radius * radius
+    })
+}
+
+/// Returns the (x, y) coordinates of every cell a circle of `radius` centred on `position` could touch.
+fn cells_under(position: Vector2<f32>, radius: f32) -> impl Iterator<Item = (i32, i32)> {
+    let (min_x, max_x) = ((position.x - radius).floor() as i32, (position.x + radius).floor() as i32);
+    let (min_y, max_y) = ((position.y - radius).floor() as i32, (position.y + radius).floor() as i32);
+
+    (min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+}
+
+/// Pushes the circle out of each solid cell it overlaps, along the line from the
+/// closest point of the cell to the centre. Returns true if the circle was moved.
+fn push_out(position: &mut Vector2<f32>, radius: f32, is_solid: impl Fn(i32, i32) -> bool) -> bool {
+    let mut pushed = false;
+
+    // The closest cells are pushed out of first. Along a flat wall that is the cell straight ahead,
+    // which leaves the circle clear of the corners where it meets the next cell of the wall.
+    // Otherwise those corners would push the circle sideways each time it slides past one.
+    let mut cells: Vec<(i32, i32)> = cells_under(*position, radius).filter(|&(x, y)| is_solid(x, y)).collect();
+    cells.sort_by(|a, b| {
+        squared_distance_to_cell(*position, a.0, a.1).total_cmp(&squared_distance_to_cell(*position, b.0, b.1))
+    });
+
+    for (x, y) in cells {
+        let (closest_x, closest_y) = closest_point_in_cell(*position, x, y);
+        let (dx, dy) = (position.x - closest_x, position.y - closest_y);
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        // A centre inside the cell has no direction to be pushed in, `move_circle` drops the step instead
+        if distance >= radius || distance == 0.0 { continue }
+
+        let depth = radius - distance + SKIN;
+        position.x += dx / distance * depth;
+        position.y += dy / distance * depth;
+        pushed = true;
+    }
+
+    pushed
+}
+
+fn closest_point_in_cell(position: Vector2<f32>, x: i32, y: i32) -> (f32, f32) {
+    (position.x.clamp(x as f32, x as f32 + 1.0), position.y.clamp(y as f32, y as f32 + 1.0))
+}
+
+fn squared_distance_to_cell(position: Vector2<f32>, x: i32, y: i32) -> f32 {
+    let (closest_x, closest_y) = closest_point_in_cell(position, x, y);
+    let (dx, dy) = (position.x - closest_x, position.y - closest_y);
+    dx * dx + dy * dy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f32 = 0.2;
+
+    /// Builds `is_solid` from rows of text, where `#` is a solid cell and the row number is y.
+    /// Anything outside of the rows is solid.
+    fn world(rows: &'static [&'static str]) -> impl Fn(i32, i32) -> bool {
+        move |x, y| {
+            let row = usize::try_from(y).ok().and_then(|y| rows.get(y));
+            let cell = row.zip(usize::try_from(x).ok()).and_then(|(row, x)| row.as_bytes().get(x));
+            cell.is_none_or(|&cell| cell == b'#')
+        }
+    }
+
+    fn assert_clear(position: Vector2<f32>, is_solid: impl Fn(i32, i32) -> bool) {
+        assert!(!overlaps(position, RADIUS, is_solid), "Circle at ({}, {}) overlaps a wall", position.x, position.y);
+    }
+
+    #[test]
+    fn huge_step_into_a_wall_stops_at_the_wall() {
+        let is_solid = world(&[
+            "##########",
+            "#....#...#",
+            "#....#...#",
+            "#....#...#",
+            "##########"
+        ]);
+
+        // Five seconds at top speed in one go, heading through the thin wall at x = 5
+        let start = Vector2::new(2.5, 1.5);
+        let end = move_circle(start, RADIUS, Vector2::new(50.0, 10.0), &is_solid);
+
+        assert_clear(end, &is_solid);
+        assert!(end.x < 5.0, "Passed through the wall to x = {}", end.x);
+        assert!(end.y > start.y, "Didn't slide along the wall, y stayed at {}", end.y);
+    }
+
+    #[test]
+    fn circle_rolls_round_a_convex_corner() {
+        let is_solid = world(&[
+            "##########",
+            "#........#",
+            "#........#",
+            "#....#...#",
+            "#........#",
+            "##########"
+        ]);
+
+        // Clips the top left corner of the pillar at (5, 3)
+        let start = Vector2::new(2.5, 2.9);
+        let end = move_circle(start, RADIUS, Vector2::new(5.0, 0.0), &is_solid);
+
+        assert_clear(end, &is_solid);
+        assert!(end.x > 7.0, "Stuck on the corner at x = {}", end.x);
+        assert!(end.y <= 3.0 - RADIUS, "Ended up inside the pillar at y = {}", end.y);
+    }
+
+    #[test]
+    fn circle_slides_along_a_wall() {
+        let is_solid = world(&[
+            "##########",
+            "#........#",
+            "#........#",
+            "##########"
+        ]);
+
+        // Pressed diagonally into the wall at y = 1
+        let start = Vector2::new(2.5, 1.0 + RADIUS);
+        let end = move_circle(start, RADIUS, Vector2::new(3.0, -1.0), &is_solid);
+
+        assert_clear(end, &is_solid);
+        assert!((end.x - 5.5).abs() < 1e-3, "Slid to x = {} instead of 5.5", end.x);
+        assert!((end.y - start.y).abs() < 1e-3, "Moved away from the wall to y = {}", end.y);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,81 @@
+//! Contains the sliding doors placed in the world.
+
+/// How far a door moves each second, as a fraction of the cell
+const DOOR_SPEED: f32 = 1.5;
+/// How far a door needs to be open before the player can fit through it
+const PASSABLE_OPEN_AMOUNT: f32 = 0.9;
+
+/// The axis a player walks along to pass through a door.
+/// The door itself lies across the middle of its cell, perpendicular to this axis.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DoorAxis {
+    X,
+    Y
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DoorState {
+    Closed,
+    Opening,
+    Open,
+    Closing
+}
+
+/// A door which slides sideways into the wall next to it.
+pub struct Door {
+    pub axis: DoorAxis,
+    pub state: DoorState,
+    /// How far the door has slid open, from 0.0 (closed) to 1.0 (open)
+    pub open_amount: f32
+}
+
+impl Door {
+    pub fn new(axis: DoorAxis) -> Door {
+        Door {
+            axis,
+            state: DoorState::Closed,
+            open_amount: 0.0
+        }
+    }
+
+    /// Starts opening the door if it is closed or closing,
+    /// and starts closing it otherwise.
+    pub fn toggle(&mut self) {
+        self.state = match self.state {
+            DoorState::Closed | DoorState::Closing => DoorState::Opening,
+            DoorState::Open | DoorState::Opening => DoorState::Closing
+        }
+    }
+
+    /// Slides the door if it is opening or closing.
+    /// `dt` is the time since the last update, in seconds.
+    /// A closing door which is `blocked` opens again, so it can't shut on whatever is in the way.
+    pub fn update(&mut self, dt: f32, blocked: bool) {
+        if blocked && self.state == DoorState::Closing {
+            self.state = DoorState::Opening;
+        }
+
+        match self.state {
+            DoorState::Opening => {
+                self.open_amount += DOOR_SPEED * dt;
+                if self.open_amount >= 1.0 {
+                    self.open_amount = 1.0;
+                    self.state = DoorState::Open;
+                }
+            }
+            DoorState::Closing => {
+                self.open_amount -= DOOR_SPEED * dt;
+                if self.open_amount <= 0.0 {
+                    self.open_amount = 0.0;
+                    self.state = DoorState::Closed;
+                }
+            }
+            DoorState::Closed | DoorState::Open => {}
+        }
+    }
+
+    /// Returns true if the door is open far enough for the player to walk through.
+    pub fn is_passable(&self) -> bool {
+        self.open_amount >= PASSABLE_OPEN_AMOUNT
+    }
+}
\ No newline at end of file
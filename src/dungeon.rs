@@ -0,0 +1,283 @@
+//! Contains the room and corridor dungeon generator.
+//!
+//! The world is split in two again and again (binary space partitioning) until the pieces
+//! are small enough to hold one room each. The two halves of every split are then joined
+//! by a corridor, so every room can be reached from every other room.
+//! Every room is surrounded by a ring of walls in its own texture, and a door is placed
+//! wherever a corridor passes through that ring.
+
+use crate::{
+    game::CellProperties,
+    grid::Grid,
+    map::Map,
+    util::{Rng, Vector2}
+};
+
+/// Wall textures a room can be given
+const ROOM_THEMES: [i32; 5] = [1, 2, 4, 5, 6];
+/// Texture id of the walls which don't belong to any room
+const CORRIDOR_TEXTURE: i32 = 3;
+const DOOR_TEXTURE: i32 = 7;
+/// Texture id of the floor of the exit room
+const EXIT_TEXTURE: i32 = 8;
+
+/// How a dungeon should be generated.
+#[derive(Clone, Copy)]
+pub struct DungeonOptions {
+    /// Size of the world along x, including the outer wall
+    pub width: usize,
+    /// Size of the world along y, including the outer wall
+    pub height: usize,
+    /// Smallest width or height of a room, in cells
+    pub min_room: usize,
+    /// Largest width or height of a room, in cells
+    pub max_room: usize,
+    pub seed: u64
+}
+
+impl Default for DungeonOptions {
+    fn default() -> Self {
+        Self {
+            width: 48,
+            height: 48,
+            min_room: 4,
+            max_room: 10,
+            seed: 0
+        }
+    }
+}
+
+/// A rectangle of cells, starting at (`x`, `y`).
+#[derive(Clone, Copy)]
+struct Area {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize
+}
+
+impl Area {
+    fn centre(&self) -> (usize, usize) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+}
+
+/// Cells a corridor runs between
+type Corridor = ((usize, usize), (usize, usize));
+
+struct Room {
+    area: Area,
+    /// Wall texture id
+    theme: i32
+}
+
+/// File names of the textures used by generated dungeons, texture id `n` is at index `n-1`.
+pub fn textures() -> Vec<String> {
+    [
+        "DUNGEONBRICKS.png", "ROUNDBRICKS.png", "GRAYWALL.png", "PIPES.png",
+        "DUNGEONCELL.png", "LAVAROCKS.png", "SPOOKYDOOR.png", "CROSSCUBE.png"
+    ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// Generates a dungeon, with the spawn in the middle of a random room
+/// and the exit in the middle of the room furthest away from it.
+pub fn generate(options: &DungeonOptions) -> Map {
+    generate_rooms(options).0
+}
+
+/// Generates a dungeon the same way as `generate`, also returning the rooms it was built from.
+fn generate_rooms(options: &DungeonOptions) -> (Map, Vec<Room>) {
+    let min_room = options.min_room.max(1);
+    let max_room = options.max_room.max(min_room);
+    // Every room needs a ring of wall around it as well as the outer wall of the world
+    let width = options.width.max(min_room + 4);
+    let height = options.height.max(min_room + 4);
+    let mut rng = Rng::new(options.seed);
+
+    let mut world = Grid::new(width, height, CORRIDOR_TEXTURE);
+    let mut rooms = Vec::new();
+    let mut corridors = Vec::new();
+
+    let inside = Area { x: 1, y: 1, width: width - 2, height: height - 2 };
+    split(inside, min_room, max_room, &mut rng, &mut rooms, &mut corridors);
+
+    for room in &rooms {
+        let Area { x, y, width, height } = room.area;
+        for ring_x in x - 1..=x + width {
+            for ring_y in y - 1..=y + height {
+                world[ring_x][ring_y] = room.theme;
+            }
+        }
+        for room_x in x..x + width {
+            for room_y in y..y + height {
+                world[room_x][room_y] = 0;
+            }
+        }
+    }
+
+    for &(from, to) in &corridors {
+        carve_corridor(&mut world, from, to, &mut rng);
+    }
+
+    let doors = place_doors(&mut world, &rooms);
+
+    let spawn_room = &rooms[rng.range(rooms.len())];
+    let spawn = spawn_room.area.centre();
+
+    // Every room is reached, as each corridor is carved open from centre to centre after the rooms
+    // and the splits join every room to the rest. Doors can be opened, so they don't block the way.
+    let reached = world.flood_fill(spawn, |x, y, cell| *cell == 0 || doors.contains(&(x, y)));
+
+    // The flood fill reaches the furthest cells last
+    let exit = reached.iter().rev()
+        .copied()
+        .find(|cell| rooms.iter().any(|room| room.area.centre() == *cell))
+        .unwrap_or(spawn);
+
+    let mut map = Map::new(textures(), world, Vector2::new(spawn.0 as f32 + 0.5, spawn.1 as f32 + 0.5));
+
+    map.metadata.name = format!("Dungeon {}x{} (seed {})", width, height, options.seed);
+    map.doors = doors;
+    map.exit = Some(exit);
+    map.cell_properties[exit.0][exit.1] = CellProperties {
+        floor_texture: EXIT_TEXTURE,
+        ..CellProperties::default()
+    };
+
+    (map, rooms)
+}
+
+/// Splits `area` until the pieces are no bigger than a room with its walls, adding a room to each piece.
+/// The rooms either side of each split are joined by adding a corridor between them.
+/// Returns the indices of the rooms added inside `area`.
+fn split(area: Area, min_room: usize, max_room: usize, rng: &mut Rng, rooms: &mut Vec<Room>, corridors: &mut Vec<Corridor>) -> Vec<usize> {
+    // Both halves must still fit a room and its walls
+    let min_piece = min_room + 2;
+    let can_split_x = area.width > max_room + 2 && area.width >= min_piece * 2;
+    let can_split_y = area.height > max_room + 2 && area.height >= min_piece * 2;
+
+    let split_x = match (can_split_x, can_split_y) {
+        (false, false) => {
+            // The room fills a random part of the piece, leaving space for its walls
+            let room_width = min_room + rng.range(max_room.min(area.width - 2) - min_room + 1);
+            let room_height = min_room + rng.range(max_room.min(area.height - 2) - min_room + 1);
+            let room = Room {
+                area: Area {
+                    x: area.x + 1 + rng.range(area.width - 2 - room_width + 1),
+                    y: area.y + 1 + rng.range(area.height - 2 - room_height + 1),
+                    width: room_width,
+                    height: room_height
+                },
+                theme: ROOM_THEMES[rng.range(ROOM_THEMES.len())]
+            };
+            rooms.push(room);
+            return vec![rooms.len() - 1]
+        }
+        (true, false) => true,
+        (false, true) => false,
+        // Split across the longer side, so rooms don't end up long and thin
+        (true, true) => area.width >= area.height
+    };
+
+    let (first, second) = if split_x {
+        let cut = min_piece + rng.range(area.width - min_piece * 2 + 1);
+        (Area { width: cut, ..area }, Area { x: area.x + cut, width: area.width - cut, ..area })
+    } else {
+        let cut = min_piece + rng.range(area.height - min_piece * 2 + 1);
+        (Area { height: cut, ..area }, Area { y: area.y + cut, height: area.height - cut, ..area })
+    };
+
+    let first = split(first, min_room, max_room, rng, rooms, corridors);
+    let second = split(second, min_room, max_room, rng, rooms, corridors);
+
+    // Join the closest pair of rooms, which keeps corridors short
+    let distance = |a: usize, b: usize| {
+        let (a, b) = (rooms[a].area.centre(), rooms[b].area.centre());
+        a.0.abs_diff(b.0) + a.1.abs_diff(b.1)
+    };
+    let closest = first.iter()
+        .flat_map(|&a| second.iter().map(move |&b| (a, b)))
+        .min_by_key(|&(a, b)| distance(a, b));
+
+    if let Some((a, b)) = closest {
+        corridors.push((rooms[a].area.centre(), rooms[b].area.centre()));
+    }
+
+    [first, second].concat()
+}
+
+/// Carves a one cell wide corridor with a single bend from `from` to `to`.
+fn carve_corridor(world: &mut Grid<i32>, from: (usize, usize), to: (usize, usize), rng: &mut Rng) {
+    let bend = if rng.chance(0.5) { (to.0, from.1) } else { (from.0, to.1) };
+
+    for (start, end) in [(from, bend), (bend, to)] {
+        for x in start.0.min(end.0)..=start.0.max(end.0) {
+            for y in start.1.min(end.1)..=start.1.max(end.1) {
+                world[x][y] = 0;
+            }
+        }
+    }
+}
+
+/// Turns corridor cells in the walls around each room into doors,
+/// where the corridor passes straight through the wall.
+/// Returns the (x, y) coordinates of the doors.
+fn place_doors(world: &mut Grid<i32>, rooms: &[Room]) -> Vec<(usize, usize)> {
+    let mut doors = Vec::new();
+
+    for room in rooms {
+        let Area { x, y, width, height } = room.area;
+
+        // Each wall cell with the step out of the room, leaving out the corners
+        let walls = (y..y + height).flat_map(|wall_y| [((x - 1, wall_y), (-1, 0)), ((x + width, wall_y), (1, 0))])
+            .chain((x..x + width).flat_map(|wall_x| [((wall_x, y - 1), (0, -1)), ((wall_x, y + height), (0, 1))]));
+
+        for ((door_x, door_y), (dx, dy)) in walls.collect::<Vec<((usize, usize), (isize, isize))>>() {
+            let outside = world[door_x.wrapping_add_signed(dx)][door_y.wrapping_add_signed(dy)];
+            // The walls either side of the door, which it slides into
+            let (side_a, side_b) = if dx != 0 {
+                (world[door_x][door_y - 1], world[door_x][door_y + 1])
+            } else {
+                (world[door_x - 1][door_y], world[door_x + 1][door_y])
+            };
+
+            if world[door_x][door_y] == 0 && outside == 0 && side_a != 0 && side_b != 0 {
+                world[door_x][door_y] = DOOR_TEXTURE;
+                doors.push((door_x, door_y));
+            }
+        }
+    }
+
+    doors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::{validate, Problem};
+
+    #[test]
+    fn every_room_can_be_reached_from_the_spawn() {
+        for (width, height) in [(12, 12), (20, 48), (48, 48), (64, 30), (100, 100)] {
+            for seed in 0..40 {
+                let options = DungeonOptions { width, height, seed, ..DungeonOptions::default() };
+                let (map, rooms) = generate_rooms(&options);
+                let name = &map.metadata.name;
+
+                for diagnostic in validate(&map) {
+                    assert!(!diagnostic.is_error(), "{name}: {}", diagnostic.problem);
+                    assert!(!matches!(diagnostic.problem, Problem::UnreachableArea { .. }), "{name}: {}", diagnostic.problem);
+                }
+
+                let spawn = (map.spawn_position.x as usize, map.spawn_position.y as usize);
+                let reached = map.world.flood_fill(spawn, |x, y, cell| *cell == 0 || map.doors.contains(&(x, y)));
+                for room in &rooms {
+                    assert!(reached.contains(&room.area.centre()), "{name}: room at {:?} can't be reached", room.area.centre());
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,180 @@
+//! Contains the in-game map editor.
+//!
+//! While the editor is open the minimap is enlarged into a grid which can be painted on,
+//! and changes show up in the 3D view straight away.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    Error,
+    game::GameContext,
+    validate::validate
+};
+
+/// Where maps are saved when they weren't loaded from a file.
+pub const UNTITLED_MAP: &str = "maps/untitled.map";
+
+/// A single cell changed by the editor.
+#[derive(Clone, Copy)]
+struct CellChange {
+    x: usize,
+    y: usize,
+    before: i32,
+    after: i32
+}
+
+pub struct Editor {
+    pub active: bool,
+    /// Cell value painted by the left mouse button, 0 erases
+    pub brush: i32,
+    /// File the map was loaded from, if it was loaded from one
+    map_path: Option<PathBuf>,
+    save_path: PathBuf,
+    /// Finished strokes, most recent last
+    history: Vec<Vec<CellChange>>,
+    /// Strokes which have been undone, most recent last
+    undone: Vec<Vec<CellChange>>,
+    /// Changes made since the mouse button was pressed
+    stroke: Vec<CellChange>
+}
+
+impl Editor {
+    /// Creates a closed editor for the map loaded from `map_path`, which it saves back to.
+    /// Maps which weren't loaded from a file are saved to `UNTITLED_MAP`.
+    pub fn new(map_path: Option<PathBuf>) -> Editor {
+        Editor {
+            active: false,
+            brush: 1,
+            save_path: map_path.clone().unwrap_or_else(|| PathBuf::from(UNTITLED_MAP)),
+            map_path,
+            history: Vec::new(),
+            undone: Vec::new(),
+            stroke: Vec::new()
+        }
+    }
+
+    pub fn save_path(&self) -> &Path {
+        &self.save_path
+    }
+
+    /// Saves to `path` instead of the file the map was loaded from.
+    pub fn set_save_path(&mut self, path: PathBuf) {
+        self.save_path = path;
+    }
+
+    /// Forgets every stroke, such as when the world has been replaced and they no longer apply.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.undone.clear();
+        self.stroke.clear();
+    }
+
+    /// Keeps the brush within the cell values of `game_context`, such as after the map has been reloaded
+    /// with fewer cell types. A brush which no longer exists is swapped for the eraser.
+    pub fn fit_brush(&mut self, game_context: &GameContext) {
+        if !(0..=game_context.cell_types.len() as i32).contains(&self.brush) {
+            self.brush = 0;
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.end_stroke();
+        self.active = !self.active;
+    }
+
+    /// Selects the next cell value, wrapping round to 0 after the last cell type.
+    pub fn next_brush(&mut self, game_context: &GameContext) {
+        self.brush = (self.brush + 1) % (game_context.cell_types.len() as i32 + 1);
+    }
+
+    /// Selects the previous cell value, wrapping round to the last cell type before 0.
+    pub fn previous_brush(&mut self, game_context: &GameContext) {
+        let n_values = game_context.cell_types.len() as i32 + 1;
+        self.brush = (self.brush - 1).rem_euclid(n_values);
+    }
+
+    /// Sets the cell at (`x`, `y`) to `value` as part of the current stroke.
+    ///
+    /// Cells which would break the map are left alone: the boundary can't be emptied,
+    /// doors can't be painted over and the player can't be walled in.
+    pub fn paint(&mut self, game_context: &mut GameContext, x: usize, y: usize, value: i32) {
+        let Some(&before) = game_context.world.get(x, y) else { return };
+
+        let player_cell = (game_context.player.position.x as usize, game_context.player.position.y as usize);
+        let blocks_player = game_context.cell_type(value).is_some_and(|cell_type| cell_type.solid);
+
+        if before == value
+            || (value == 0 && game_context.world.on_boundary(x, y))
+            || game_context.doors.contains_key(&(x, y))
+            || ((x, y) == player_cell && blocks_player)
+        {
+            return
+        }
+
+        game_context.world[x][y] = value;
+        self.stroke.push(CellChange { x, y, before, after: value });
+    }
+
+    /// Finishes the current stroke, so it is undone in one go.
+    pub fn end_stroke(&mut self) {
+        if self.stroke.is_empty() { return }
+
+        self.history.push(std::mem::take(&mut self.stroke));
+        self.undone.clear();
+    }
+
+    /// Reverts the most recent stroke.
+    pub fn undo(&mut self, game_context: &mut GameContext) {
+        self.end_stroke();
+        let Some(stroke) = self.history.pop() else { return };
+
+        for change in stroke.iter().rev() {
+            game_context.world[change.x][change.y] = change.before;
+        }
+        self.undone.push(stroke);
+    }
+
+    /// Applies the most recently undone stroke again.
+    pub fn redo(&mut self, game_context: &mut GameContext) {
+        self.end_stroke();
+        let Some(stroke) = self.undone.pop() else { return };
+
+        for change in &stroke {
+            game_context.world[change.x][change.y] = change.after;
+        }
+        self.history.push(stroke);
+    }
+
+    /// Saves the world to the map file, unless it has errors which would stop it from loading.
+    /// Maps with an `[image]` can't be saved over, as the edited cells can only be written as `[cells]`.
+    pub fn save(&mut self, game_context: &GameContext) -> Result<(), Error> {
+        self.end_stroke();
+
+        if let Some((layout_image, _)) = &game_context.layout_image {
+            if self.map_path.as_ref() == Some(&self.save_path) {
+                return Err(format!(
+                    "EDITOR - Not saving over '{}', its cells are read from '{}' which the editor can't write to.\n\
+                    Start the game with --save-as <file.map> to save the edited map to another file",
+                    self.save_path.display(), layout_image.display()
+                ).into())
+            }
+        }
+
+        let map = game_context.to_map();
+
+        let errors: Vec<String> = validate(&map)
+            .into_iter()
+            .filter(|diagnostic| diagnostic.is_error())
+            .map(|diagnostic| format!("WORLD - {}", diagnostic.problem))
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(format!("EDITOR - Not saving '{}', the map has errors:\n{}", self.save_path.display(), errors.join("\n")).into())
+        }
+
+        map.save(&self.save_path)?;
+        println!("Saved map to '{}'", self.save_path.display());
+
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -2,12 +2,31 @@
 
 use crate::{
     Error,
+    editor::Editor,
+    fog::Fog,
+    hot_reload::FileWatcher,
+    input::{axis_value, GamepadSettings, MouseLook, Sticks},
+    texture::texture_path,
     render::Renderer,
-    game::GameContext
+    game::GameContext,
+    map::Map,
+    player::Player
 };
 
-use std::time::{Instant, Duration};
-use sdl2::{event::Event, keyboard::{Keycode, Scancode}};
+use std::{path::PathBuf, time::{Instant, Duration}};
+use sdl2::{
+    GameControllerSubsystem,
+    controller::{Axis, Button, GameController},
+    event::{Event, WindowEvent},
+    keyboard::{Keycode, Mod, Scancode},
+    mouse::MouseButton
+};
+
+/// Length of one simulation step, the game advances by this much time at once whatever the frame rate is
+const SIMULATION_STEP: Duration = Duration::from_nanos(1_000_000_000 / 120);
+/// Most simulation steps run in a single frame. If the game falls further behind than this,
+/// the extra time is dropped and the game slows down, rather than each frame taking longer to catch up.
+const MAX_STEPS_PER_FRAME: u32 = 12;
 
 /// Serves as the core of the 3D rendering application.
 /// 
@@ -17,19 +36,150 @@ pub struct Engine {
     sdl_context: sdl2::Sdl,
     game_context: GameContext,
     renderer: Renderer,
+    editor: Editor,
+    /// Size of the minimap cells while the editor is closed
+    minimap_scale: u32,
+    /// Whether the minimap is shown while the editor is closed
+    show_minimap: bool,
+    /// File the map was loaded from, if it was loaded from one
+    map_path: Option<PathBuf>,
+    /// Watches the map and its textures, so they can be reloaded when they change
+    watcher: FileWatcher,
     delta_time: Duration,
+    /// Time which hasn't been simulated yet, always less than `SIMULATION_STEP` between frames
+    accumulator: Duration,
+    /// The player before the last simulation step, so they can be drawn between steps
+    previous_player: Player,
+    mouse_look: MouseLook,
+    /// The mouse is hidden and turns the view while grabbed
+    mouse_grabbed: bool,
+    controller_subsystem: GameControllerSubsystem,
+    /// Game controllers which are plugged in, all of them control the player
+    controllers: Vec<GameController>,
+    gamepad: GamepadSettings
 }
 
 impl Engine {
-    pub fn new(window_title: &'static str, window_width: u32, window_height: u32) -> Result<Self, Error> {
+    /// Opens a window to play `game_context` in.
+    /// The fog and sky are set up from the map metadata, and the textures are reloaded when they change.
+    pub fn new(window_title: &'static str, window_width: u32, window_height: u32, game_context: GameContext) -> Result<Self, Error> {
         let sdl_context = sdl2::init()?;
-        let game_context = GameContext::new()?;
-        let renderer = Renderer::new(&sdl_context, window_title, window_width, window_height)?;
+        // Controllers which are already plugged in are reported as added once the loop starts
+        let controller_subsystem = sdl_context.game_controller()?;
+        let renderer = Renderer::new(&sdl_context, window_title, window_width, window_height, &game_context)?;
         let delta_time = Duration::ZERO;
+        let minimap_scale = renderer.render_context.minimap_scale;
+        let show_minimap = renderer.render_context.show_minimap;
+        let previous_player = game_context.player.clone();
+
+        let mut engine = Self {
+            sdl_context, game_context, renderer, delta_time, previous_player, controller_subsystem,
+            accumulator: Duration::ZERO,
+            mouse_look: MouseLook::default(),
+            mouse_grabbed: false,
+            controllers: Vec::new(),
+            gamepad: GamepadSettings::default(),
+            editor: Editor::new(None),
+            minimap_scale,
+            show_minimap,
+            map_path: None,
+            watcher: FileWatcher::new()
+        };
+
+        engine.apply_metadata();
+        engine.watch_files();
+
+        Ok(engine)
+    }
+
+    /// Sets the fog and sky from the metadata of the current map.
+    fn apply_metadata(&mut self) {
+        self.set_fog(self.game_context.metadata.fog);
+        self.renderer.set_sky(self.game_context.metadata.sky.as_ref());
+    }
+
+    /// Watches the map file, its layout image, its textures and its sky for changes.
+    fn watch_files(&mut self) {
+        self.watcher.clear();
+
+        if let Some(map_path) = &self.map_path {
+            self.watcher.watch(map_path);
+        }
+        if let Some((layout_image, _)) = &self.game_context.layout_image {
+            self.watcher.watch(layout_image);
+        }
+
+        let sky = self.game_context.metadata.sky.as_ref().map(|(sky_texture, _)| sky_texture);
+        for file_name in self.game_context.textures.iter().chain(sky) {
+            if let Ok(path) = texture_path(file_name) {
+                self.watcher.watch(path);
+            }
+        }
+    }
+
+    /// Reloads the map and textures which have changed on disk since they were loaded.
+    /// Problems are reported and the old version is kept, so a half written file can't end the game.
+    fn hot_reload(&mut self) {
+        let changed = self.watcher.changed_files();
+        if changed.is_empty() { return }
+
+        let layout_image = self.game_context.layout_image.as_ref().map(|(path, _)| path);
+        let map_changed = self.map_path.iter().chain(layout_image).any(|path| changed.contains(path));
+
+        if let Some((map_path, reloaded)) = map_changed.then(|| self.load_world()).flatten() {
+            match reloaded {
+                Ok(reloaded) => {
+                    self.game_context.replace_world(reloaded);
+                    // The player may have moved back to the spawn, which shouldn't be drawn as a slide
+                    self.previous_player = self.game_context.player.clone();
+                    // The edits were made to the old world, which may have had more cell types
+                    self.editor.clear_history();
+                    self.editor.fit_brush(&self.game_context);
+
+                    // The textures may have been swapped for others, so they are all reloaded
+                    if let Err(err) = self.renderer.load_textures(&self.game_context.textures) {
+                        println!("WARN: Could not reload the textures of '{}' - {err}", map_path.display());
+                    }
+                    self.apply_metadata();
+
+                    self.watch_files();
+                    println!("Reloaded map '{}'", map_path.display());
+                }
+                Err(err) => println!("WARN: Could not reload map '{}', keeping the old version -\n{err}", map_path.display())
+            }
+            return
+        }
+
+        for (i, file_name) in self.game_context.textures.iter().enumerate() {
+            if !texture_path(file_name).is_ok_and(|path| changed.contains(&path)) { continue }
+
+            match self.renderer.reload_texture(i, file_name) {
+                Ok(()) => println!("Reloaded texture '{file_name}'"),
+                Err(err) => println!("WARN: Could not reload texture '{file_name}', keeping the old version - {err}")
+            }
+        }
 
-        Ok(Self {
-            sdl_context, game_context, renderer, delta_time
-        })
+        if let Some((sky_texture, _)) = &self.game_context.metadata.sky {
+            if texture_path(sky_texture).is_ok_and(|path| changed.contains(&path)) {
+                match self.renderer.set_sky_texture(sky_texture) {
+                    Ok(()) => println!("Reloaded sky '{sky_texture}'"),
+                    Err(err) => println!("WARN: Could not reload sky '{sky_texture}', keeping the old version - {err}")
+                }
+            }
+        }
+    }
+
+    /// Loads the current map from disk again, along with the file it was loaded from.
+    /// Maps without a map file are read from their layout image, with the same textures as before.
+    fn load_world(&self) -> Option<(PathBuf, Result<GameContext, Error>)> {
+        if let Some(map_path) = &self.map_path {
+            return Some((map_path.clone(), GameContext::load(map_path)))
+        }
+
+        let (path, palette) = self.game_context.layout_image.as_ref()?;
+        let reloaded = Map::from_image(path, palette, self.game_context.textures.clone())
+            .and_then(|map| Ok(GameContext::from_map(&map)?));
+        Some((path.clone(), reloaded))
     }
 
     /// Sets the target frames per second (FPS) for rendering engine.
@@ -42,6 +192,73 @@ impl Engine {
             else { Duration::from_millis((1000/fps) as u64) };
     }
 
+    /// Sets the map file the editor saves to, otherwise `editor::UNTITLED_MAP` is used.
+    /// The map is also reloaded when the file changes.
+    pub fn set_map_path(&mut self, path: PathBuf) {
+        self.editor = Editor::new(Some(path.clone()));
+        self.map_path = Some(path);
+        self.watch_files();
+    }
+
+    /// Makes the editor save to `path` rather than the map file, must be called after `set_map_path`.
+    pub fn set_save_path(&mut self, path: PathBuf) {
+        self.editor.set_save_path(path);
+    }
+
+    /// Sets how far the view turns for each pixel the mouse moves, see `MouseLook::sensitivity`.
+    pub fn set_mouse_sensitivity(&mut self, sensitivity: f32) {
+        self.mouse_look.sensitivity = sensitivity;
+    }
+
+    /// Sets whether moving the mouse up looks down.
+    pub fn set_invert_mouse(&mut self, invert: bool) {
+        self.mouse_look.invert = invert;
+    }
+
+    /// Grabs or releases the mouse. While grabbed the cursor is hidden
+    /// and kept in the window, and moving the mouse looks around.
+    pub fn grab_mouse(&mut self, grab: bool) {
+        self.sdl_context.mouse().set_relative_mouse_mode(grab);
+        self.mouse_grabbed = grab;
+    }
+
+    /// Opens the controller at `joystick_index`, so it can be played with.
+    fn connect_controller(&mut self, joystick_index: u32) {
+        match self.controller_subsystem.open(joystick_index) {
+            Ok(controller) => {
+                if self.controllers.iter().any(|open| open.instance_id() == controller.instance_id()) { return }
+
+                println!("Connected controller '{}'", controller.name());
+                self.controllers.push(controller);
+            }
+            Err(err) => println!("WARN: Could not open controller {joystick_index} - {err}")
+        }
+    }
+
+    /// Closes the controller with the joystick instance id `id`, once it has been unplugged.
+    fn disconnect_controller(&mut self, id: u32) {
+        self.controllers.retain(|controller| {
+            let unplugged = controller.instance_id() == id;
+            if unplugged { println!("Disconnected controller '{}'", controller.name()) }
+            !unplugged
+        });
+    }
+
+    /// Turns and tilts the view straight away, rather than on the next simulation step,
+    /// so looking around stays as responsive as the frame rate allows.
+    fn look(&mut self, turn: f32, tilt: f32) {
+        // The previous player is turned as well, otherwise the turn would be drawn as a sweep
+        for player in [&mut self.game_context.player, &mut self.previous_player] {
+            player.rotate(turn);
+            player.tilt(tilt);
+        }
+    }
+
+    /// Sets the distance fog used by the renderer, `None` disables fog.
+    pub fn set_fog(&mut self, fog: Option<Fog>) {
+        self.renderer.render_context.fog = fog;
+    }
+
     /// Initiates the main game loop, stopping when a quit event
     /// is recieved or an error occurs.
     pub fn main_loop(&mut self) -> Result<(), Error> {
@@ -55,9 +272,11 @@ impl Engine {
             // Break the loop if handle_events returns true
             if self.handle_events()? { break }
 
-            self.game_context.tick(self.delta_time);
+            self.hot_reload();
+            self.simulate();
 
-            self.renderer.draw(&self.game_context)?;
+            self.update_editor_view()?;
+            self.draw()?;
 
             self.wait(frame_start);
         }
@@ -65,20 +284,135 @@ impl Engine {
         Ok(())
     }
 
+    /// Runs as many simulation steps as fit in the time since the last frame,
+    /// so the game plays the same at any frame rate.
+    fn simulate(&mut self) {
+        self.accumulator += self.delta_time;
+
+        let mut steps = 0;
+        while self.accumulator >= SIMULATION_STEP {
+            if steps == MAX_STEPS_PER_FRAME {
+                self.accumulator = Duration::ZERO;
+                break
+            }
+
+            self.previous_player = self.game_context.player.clone();
+            self.game_context.tick(SIMULATION_STEP);
+            self.accumulator -= SIMULATION_STEP;
+            steps += 1;
+        }
+
+        // Once a tick has processed the movements, reset the intents.
+        // Frames which run no ticks keep them, so a short press isn't lost.
+        if steps > 0 {
+            self.game_context.clear_intents();
+        }
+    }
+
+    /// Draws the current frame, with the player part way between the last two simulation steps
+    /// according to how much unsimulated time is left over.
+    fn draw(&mut self) -> Result<(), Error> {
+        let alpha = self.accumulator.as_secs_f32() / SIMULATION_STEP.as_secs_f32();
+        let interpolated = self.game_context.player.interpolate(&self.previous_player, alpha);
+
+        let simulated = std::mem::replace(&mut self.game_context.player, interpolated);
+        let result = self.renderer.draw(&self.game_context);
+        self.game_context.player = simulated;
+
+        result
+    }
+
+    /// Enlarges the minimap to fill most of the window while the editor is open,
+    /// and shows the texture picker.
+    fn update_editor_view(&mut self) -> Result<(), Error> {
+        let render_context = &mut self.renderer.render_context;
+
+        if !self.editor.active {
+            render_context.editor_brush = None;
+            render_context.minimap_cursor = None;
+            render_context.minimap_scale = self.minimap_scale;
+            render_context.show_minimap = self.show_minimap;
+            return Ok(())
+        }
+
+        // Leave space above the minimap for the texture picker
+        let (screen_width, screen_height) = self.renderer.output_size()?;
+        let world = &self.game_context.world;
+        let scale = (screen_width as usize * 3 / 4 / world.height().max(1))
+            .min(screen_height as usize * 3 / 4 / world.width().max(1))
+            .max(1);
+
+        let render_context = &mut self.renderer.render_context;
+        render_context.editor_brush = Some(self.editor.brush);
+        render_context.show_minimap = true;
+        render_context.minimap_scale = scale as u32;
+
+        Ok(())
+    }
+
+    /// Handles a mouse press or drag in the editor.
+    /// Clicking the texture picker selects a brush, and the minimap is painted
+    /// with the left button and erased with the right button.
+    fn edit_at(&mut self, x: i32, y: i32, button: Option<MouseButton>, pressed: bool) {
+        let slots = self.game_context.cell_types.len() + 1;
+        if pressed {
+            if let Some(slot) = self.renderer.picker_slot_at(x, y, slots) {
+                self.editor.brush = slot as i32;
+                return
+            }
+        }
+
+        let cell = self.renderer.minimap_cell_at(x, y);
+        self.renderer.render_context.minimap_cursor = cell;
+
+        let Some((cell_x, cell_y)) = cell else { return };
+
+        match button {
+            Some(MouseButton::Left) => self.editor.paint(&mut self.game_context, cell_x, cell_y, self.editor.brush),
+            Some(MouseButton::Right) => self.editor.paint(&mut self.game_context, cell_x, cell_y, 0),
+            _ => {}
+        }
+    }
+
+    /// Handles the editor shortcuts, returning true if the key was used.
+    fn handle_editor_key(&mut self, keycode: Keycode, keymod: Mod) -> bool {
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+
+        match keycode {
+            Keycode::Z if ctrl && shift => self.editor.redo(&mut self.game_context),
+            Keycode::Z if ctrl => self.editor.undo(&mut self.game_context),
+            Keycode::Y if ctrl => self.editor.redo(&mut self.game_context),
+            Keycode::S if ctrl => {
+                // A failed save shouldn't close the game, the map can still be fixed
+                match self.editor.save(&self.game_context) {
+                    // The map on disk now matches the world, so there is nothing to reload
+                    Ok(()) => self.watcher.mark_seen(self.editor.save_path()),
+                    Err(err) => println!("WARN: {err}")
+                }
+            }
+            Keycode::LeftBracket => self.editor.previous_brush(&self.game_context),
+            Keycode::RightBracket => self.editor.next_brush(&self.game_context),
+            _ => return false
+        }
+
+        true
+    }
+
     /// Compares the desired frame time with the current frame time,
     /// subtracting the values and sleeping for that duration.
     /// 
-    /// Calclates current frame time based of `frame_start`.
+    /// Calclates current frame time based of `frame_start`,
+    /// including the time spent sleeping, as that still has to be simulated.
     fn wait(&mut self, frame_start: Instant) {
 
         let frame_time = Instant::now().duration_since(frame_start);
         
         if frame_time <= self.renderer.render_context.desired_frame_time {
             std::thread::sleep(self.renderer.render_context.desired_frame_time-frame_time);
-            self.delta_time = self.renderer.render_context.desired_frame_time
-        } else {
-            self.delta_time = frame_time;
         }
+
+        self.delta_time = Instant::now().duration_since(frame_start);
     }
 
     /// Handles all SDL Events.
@@ -91,11 +425,68 @@ impl Engine {
 
         // Iterate over events
         for event in event_pump.poll_iter() {
+            // Editor shortcuts, keys the editor doesn't use are left for the game
+            if let Event::KeyDown{keycode: Some(keycode), keymod, ..} = event {
+                if self.editor.active && self.handle_editor_key(keycode, keymod) { continue }
+            }
+
             match event {
                 // Quit event
                     Event::Quit{..} |
                     Event::KeyDown{keycode: Some(Keycode::Escape), ..}
                     => return Ok(true),  // `true` represents that we want to quit
+                // Use the door in front of the player
+                    Event::KeyDown{keycode: Some(Keycode::E), repeat: false, ..}
+                    => self.game_context.use_door(),
+                // Open or close the map editor, which needs the mouse cursor
+                    Event::KeyDown{keycode: Some(Keycode::Tab), repeat: false, ..}
+                    => {
+                        self.editor.toggle();
+                        if self.editor.active { self.grab_mouse(false) }
+                    }
+                // Paint, erase or pick a brush in the editor
+                    Event::MouseButtonDown{x, y, mouse_btn, ..} if self.editor.active
+                    => self.edit_at(x, y, Some(mouse_btn), true),
+                    Event::MouseMotion{x, y, mousestate, ..} if self.editor.active
+                    => {
+                        let button = if mousestate.left() {
+                            Some(MouseButton::Left)
+                        } else if mousestate.right() {
+                            Some(MouseButton::Right)
+                        } else {
+                            None
+                        };
+                        self.edit_at(x, y, button, false);
+                    }
+                    Event::MouseButtonUp{..} if self.editor.active
+                    => self.editor.end_stroke(),
+                    Event::MouseWheel{y, ..} if self.editor.active
+                    => if y > 0 { self.editor.next_brush(&self.game_context) } else if y < 0 { self.editor.previous_brush(&self.game_context) },
+                // Show or hide the minimap
+                    Event::KeyDown{keycode: Some(Keycode::M), repeat: false, ..} |
+                    Event::ControllerButtonDown{button: Button::Y, ..}
+                    => self.show_minimap = !self.show_minimap,
+                // Controller buttons, the held ones are read with the sticks below.
+                // The game has nothing to fire yet, so the right shoulder and trigger are left free for it.
+                    Event::ControllerButtonDown{button: Button::X, ..}
+                    => self.game_context.use_door(),
+                // Controllers can be plugged in and out while playing
+                    Event::ControllerDeviceAdded{which, ..}
+                    => self.connect_controller(which),
+                    Event::ControllerDeviceRemoved{which, ..}
+                    => self.disconnect_controller(which),
+                // Grab or release the mouse
+                    Event::KeyDown{keycode: Some(Keycode::G), repeat: false, ..}
+                    => self.grab_mouse(!self.mouse_grabbed),
+                // Look around with the grabbed mouse
+                    Event::MouseMotion{xrel, yrel, ..} if self.mouse_grabbed
+                    => {
+                        let (turn, tilt) = self.mouse_look.look(xrel, yrel);
+                        self.look(turn, tilt);
+                    }
+                // Let go of the mouse when switching to another window
+                    Event::Window{win_event: WindowEvent::FocusLost, ..}
+                    => self.grab_mouse(false),
                 // Default
                     _ => {}
             }
@@ -107,10 +498,38 @@ impl Engine {
         // However such information is already stored in the keyboard state.
         let ks = event_pump.keyboard_state();
 
+        // Held input is read again below, rather than adding up over frames which run no ticks
+        self.game_context.release_held_intents();
+
         if ks.is_scancode_pressed(Scancode::W) { self.game_context.move_forward()   }
         if ks.is_scancode_pressed(Scancode::S) { self.game_context.move_backwards() }
-        if ks.is_scancode_pressed(Scancode::A) { self.game_context.turn_left()      }
-        if ks.is_scancode_pressed(Scancode::D) { self.game_context.turn_right()     }
+        if ks.is_scancode_pressed(Scancode::A) { self.game_context.strafe_left()    }
+        if ks.is_scancode_pressed(Scancode::D) { self.game_context.strafe_right()   }
+
+        if ks.is_scancode_pressed(Scancode::Left)  { self.game_context.turn_left()  }
+        if ks.is_scancode_pressed(Scancode::Right) { self.game_context.turn_right() }
+
+        if ks.is_scancode_pressed(Scancode::Up)    { self.game_context.look_up()   }
+        if ks.is_scancode_pressed(Scancode::Down)  { self.game_context.look_down() }
+        if ks.is_scancode_pressed(Scancode::Space) { self.game_context.jump()      }
+        if ks.is_scancode_pressed(Scancode::LCtrl) { self.game_context.crouch()    }
+        if ks.is_scancode_pressed(Scancode::LShift) { self.game_context.sprint()   }
+
+        for controller in &self.controllers {
+            let sticks = Sticks {
+                left_x: axis_value(controller.axis(Axis::LeftX)),
+                left_y: axis_value(controller.axis(Axis::LeftY)),
+                right_x: axis_value(controller.axis(Axis::RightX)),
+                right_y: axis_value(controller.axis(Axis::RightY))
+            };
+            self.game_context.steer(self.gamepad.intents(&sticks));
+
+            if controller.button(Button::A) { self.game_context.jump()   }
+            if controller.button(Button::B) { self.game_context.crouch() }
+            if controller.button(Button::LeftStick) || axis_value(controller.axis(Axis::TriggerLeft)) > 0.5 {
+                self.game_context.sprint()
+            }
+        }
 
         Ok(false)
     }
@@ -0,0 +1,62 @@
+//! Contains the distance fog applied by the renderer.
+
+/// How the fog thickens between its start and end distance.
+#[derive(Clone, Copy)]
+pub enum FogFalloff {
+    /// Fog thickens at a constant rate
+    Linear,
+    /// Fog thickens quickly near the start distance and slowly towards the end.
+    /// Higher densities make the fog thicken faster.
+    Exponential(f32)
+}
+
+/// Blends pixels towards a colour the further away they are.
+#[derive(Clone, Copy)]
+pub struct Fog {
+    pub colour: u32,
+    /// Distance at which the fog begins
+    pub start: f32,
+    /// Distance at which everything is completely fogged
+    pub end: f32,
+    pub falloff: FogFalloff
+}
+
+impl Fog {
+    pub fn new(colour: u32, start: f32, end: f32, falloff: FogFalloff) -> Fog {
+        Fog { colour, start, end, falloff }
+    }
+
+    /// Returns how much of the fog colour should be mixed into
+    /// a pixel at `distance`, from 0.0 (none) to 1.0 (only fog).
+    pub fn amount(&self, distance: f32) -> f32 {
+        if distance <= self.start { return 0.0 }
+        if distance >= self.end { return 1.0 }
+
+        let t = (distance - self.start) / (self.end - self.start);
+
+        match self.falloff {
+            FogFalloff::Linear => t,
+            FogFalloff::Exponential(density) if density > 0.0 => {
+                // Scaled so the fog is still fully opaque at the end distance
+                (1.0 - (-density * t).exp()) / (1.0 - (-density).exp())
+            }
+            FogFalloff::Exponential(_) => t
+        }
+    }
+
+    /// Blends `colour` towards the fog colour based on `distance`.
+    /// The alpha of `colour` is kept as is.
+    pub fn apply(&self, colour: u32, distance: f32) -> u32 {
+        let amount = self.amount(distance);
+
+        if amount <= 0.0 { return colour }
+
+        let mix = |shift: u32| {
+            let c = ((colour >> shift) & 0xFF) as f32;
+            let f = ((self.colour >> shift) & 0xFF) as f32;
+            (c + (f - c) * amount) as u32
+        };
+
+        mix(24) << 24 | mix(16) << 16 | mix(8) << 8 | colour & 0xFF
+    }
+}
\ No newline at end of file
@@ -1,170 +1,485 @@
-use std::time::Duration;
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}, time::Duration};
 
-use crate::{player::Player, Error, texture::N_TEXTURES};
+use crate::{
+    cell::CellType,
+    collision::{move_circle, overlaps},
+    door::Door,
+    grid::Grid,
+    image_map::Palette,
+    input::StickIntents,
+    map::{Map, Metadata, DEFAULT_MAP},
+    player::Player,
+    sprite::Sprite,
+    util::Vector2,
+    validate::{door_axis, validate, Diagnostic},
+    Error
+};
 
-pub const WORLD_SIZE: usize = 24;
+/// The part of the world a `Diagnostic` was found in.
+#[derive(Debug, Clone, Copy)]
+pub enum WorldItem {
+    Cell(usize, usize),
+    /// Index into the door list
+    Door(usize),
+    /// Index into the sprite list
+    Sprite(usize),
+    /// Cell type id, as used in the world
+    CellType(i32),
+    Spawn,
+    Exit,
+    /// The world as a whole
+    World
+}
+
+/// The problems which stop a world from being played.
+pub struct WorldError {
+    /// Every error found in the world, see `validate::validate`,
+    /// described with where it is in the map file if there is one
+    pub errors: Vec<(Diagnostic, String)>
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let descriptions: Vec<&str> = self.errors.iter().map(|(_, description)| description.as_str()).collect();
+        write!(f, "{}", descriptions.join("\n"))
+    }
+}
+
+// Errors returned from `main` are printed with `Debug`, which should read the same as `Display`
+impl fmt::Debug for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for WorldError {}
+
+/// Information about a cell which isn't part of the wall layout.
+/// 
+/// Texture ids follow the same numbering as the world cells,
+/// with 0 meaning no texture.
+#[derive(Clone, Copy)]
+pub struct CellProperties {
+    /// Texture drawn on the floor, otherwise the flat floor colour is used
+    pub floor_texture: i32,
+    /// Texture drawn on the ceiling, otherwise the flat ceiling colour is used
+    pub ceiling_texture: i32,
+    /// Height of the wall in this cell, relative to a standard wall.
+    /// Walls behind shorter walls can be seen over them.
+    pub wall_height: f32,
+    /// The sky is drawn instead of the ceiling above this cell
+    pub open_sky: bool
+}
+
+impl Default for CellProperties {
+    fn default() -> Self {
+        Self {
+            floor_texture: 0,
+            ceiling_texture: 0,
+            wall_height: 1.0,
+            open_sky: false
+        }
+    }
+}
+
+/// What the player is trying to do, gathered from the input since the intents were last cleared.
+/// The axes go from -1.0 to 1.0, where anything further is treated as 1.0.
+#[derive(Clone, Copy, Default)]
+struct Intents {
+    /// Positive moves forwards and negative moves backwards
+    forward: f32,
+    /// Positive strafes right and negative strafes left
+    strafe: f32,
+    /// Positive turns left and negative turns right
+    turn: f32,
+    /// Positive looks up and negative looks down
+    look: f32,
+    jump: bool,
+    crouch: bool,
+    sprint: bool
+}
 
 /// Stores all the information about the game state
 pub struct GameContext {
     pub player: Player,
-    pub world: [[i32; WORLD_SIZE]; WORLD_SIZE],
-    move_state: [bool; 4]
+    /// Wall layout, indexed as `world[x][y]`.
+    /// 0 is an empty cell, anything else is the id of a cell type in `cell_types`.
+    pub world: Grid<i32>,
+    /// Cell type id `n` is at index `n-1`
+    pub cell_types: Vec<CellType>,
+    /// Always the same size as `world`
+    pub cell_properties: Grid<CellProperties>,
+    pub sprites: Vec<Sprite>,
+    /// Door state, keyed by the (x, y) coordinates of the door cell
+    pub doors: HashMap<(usize, usize), Door>,
+    /// Cell the player is trying to reach, if the map has one
+    pub exit: Option<(usize, usize)>,
+    /// Where the player starts, kept so the map can be saved again
+    pub spawn_position: Vector2<f32>,
+    pub spawn_direction: Vector2<f32>,
+    pub metadata: Metadata,
+    /// File names of the textures in the `/img/` directory, texture id `n` is at index `n-1`
+    pub textures: Vec<String>,
+    /// Image the cells were read from and its palette, so the world can be reloaded when it changes
+    pub layout_image: Option<(PathBuf, Palette)>,
+    intents: Intents
 }
 
 impl GameContext {
+    /// Loads the default map, see `map::DEFAULT_MAP`.
     pub fn new() -> Result<Self, Error> {
+        Self::load(DEFAULT_MAP)
+    }
+
+    /// Loads the map file at `path`, errors point at the line and column of the problem.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let map = Map::load(path)?;
+
+        Ok(Self::from_map(&map)?)
+    }
+
+    /// Checks the map for errors and builds the game state from it.
+    /// Warnings are printed, and don't stop the map from being played.
+    pub fn from_map(map: &Map) -> Result<Self, WorldError> {
+        let (errors, warnings): (Vec<Diagnostic>, Vec<Diagnostic>) = validate(map)
+            .into_iter()
+            .partition(Diagnostic::is_error);
 
-        // The world object is a 2D array of cells ranging from 0-9.
-        // Current limitations of the engine require world to have
-        // square dimensions, will likely change in future.
-        let world: [[i32; WORLD_SIZE]; WORLD_SIZE] = [
-            [1,1,2,1,1,1,2,1,1,1,2,1,1,1,2,1,1,1,2,1,1,3,3,1],
-            [1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,1],
-            [3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,1],
-            [3,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,3,0,0,0,0,0,0,1],
-            [1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1,0,0,1],
-            [1,1,0,1,1,1,2,1,1,1,0,1,1,1,0,1,1,1,0,1,1,0,0,1],
-            [1,0,0,0,1,0,0,0,0,0,0,0,1,1,0,1,1,0,0,0,1,0,0,1],
-            [2,0,0,0,2,0,0,0,0,0,0,0,1,2,0,2,1,0,0,0,1,0,0,1],
-            [1,0,0,8,1,0,0,0,0,0,0,0,1,1,0,1,1,0,0,0,1,0,0,1],
-            [1,1,2,1,1,1,2,1,1,1,2,1,1,1,0,1,1,1,0,1,1,0,0,1],
-            [7,7,7,7,7,7,7,7,7,7,7,7,1,0,0,0,0,0,0,0,0,0,0,1],
-            [7,0,0,0,0,0,0,0,0,0,0,7,1,0,0,0,0,0,0,0,0,0,0,1],
-            [7,0,0,0,0,0,0,0,0,0,0,7,1,0,0,0,0,0,0,0,1,0,0,1],
-            [7,0,0,0,0,0,0,0,0,0,0,7,1,0,0,0,0,0,0,0,1,3,3,1],
-            [7,0,0,0,0,0,0,0,0,0,0,7,1,0,0,0,0,0,0,0,1,1,1,1],
-            [7,0,0,0,0,0,0,0,0,0,0,7,1,0,0,0,0,0,0,0,1,1,1,1],
-            [7,0,0,0,0,0,0,0,0,0,0,7,1,0,0,0,0,0,0,0,1,3,3,1],
-            [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1,0,0,1],
-            [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-            [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-            [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-            [1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-            [1,2,3,4,5,6,7,8,9,0,0,0,0,0,0,0,0,0,0,0,0,0,0,1],
-            [1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1]
-        ];
-
-        // Check for error in the world
-        for (y, row) in world.iter().enumerate() {
-            for (x, cell) in row.iter().enumerate() {
-                // If the current cell is on the boundrary of the world
-                if x == 0 || y == 0 || x == WORLD_SIZE-1 || y == WORLD_SIZE-1 {
-                    if cell == &0 {
-                        return Err(format!("WORLD - World contains exposed wall at ({x}, {y})").into())
-                    }
-                }
-                // If the current is above or below the set of valid cells
-                if cell > &N_TEXTURES.try_into()? || cell < &0 {
-                    return Err(format!("WORLD - World contains invalid wall at ({x}, {y})").into())
-                }
+        for warning in &warnings {
+            println!("WARN: {}", map.describe_error(warning.item, &warning.problem.to_string()));
+        }
+
+        if !errors.is_empty() {
+            let errors = errors.into_iter()
+                .map(|error| {
+                    let description = map.describe_error(error.item, &error.problem.to_string());
+                    (error, description)
+                })
+                .collect();
+            return Err(WorldError { errors })
+        }
+
+        let world = &map.world;
+        let cell_properties = &map.cell_properties;
+
+        let mut doors = HashMap::new();
+        for &(x, y) in &map.doors {
+            if let Some(axis) = door_axis(world, x, y) {
+                doors.insert((x, y), Door::new(axis));
             }
         }
 
+        let mut player = Player::new();
+        player.position = map.spawn_position;
+        player.face(map.spawn_direction);
 
         Ok(Self{
-            player: Player::new(),
-            world,
-            move_state: [false; 4]
+            player,
+            world: world.clone(),
+            cell_types: map.cell_types.clone(),
+            cell_properties: cell_properties.clone(),
+            sprites: map.sprites.clone(),
+            doors,
+            exit: map.exit,
+            spawn_position: map.spawn_position,
+            spawn_direction: map.spawn_direction,
+            metadata: map.metadata.clone(),
+            textures: map.textures.clone(),
+            layout_image: map.layout_image.clone(),
+            intents: Intents::default()
         })
 
     }
 
+    /// Builds a map of the current world, such as after it has been edited.
+    /// Doors are saved closed and the player is saved at the spawn.
+    pub fn to_map(&self) -> Map {
+        let mut map = Map::new(self.textures.clone(), self.world.clone(), self.spawn_position);
+
+        let mut doors: Vec<(usize, usize)> = self.doors.keys().copied().collect();
+        doors.sort();
+
+        map.metadata = self.metadata.clone();
+        map.cell_types = self.cell_types.clone();
+        map.spawn_direction = self.spawn_direction;
+        map.exit = self.exit;
+        map.cell_properties = self.cell_properties.clone();
+        map.doors = doors;
+        map.sprites = self.sprites.clone();
+        map.layout_image = self.layout_image.clone();
+
+        map
+    }
+
+    /// Swaps in a reloaded copy of the map, such as after the map file has changed.
+    /// The player stays where they are if they still fit there, otherwise they go back to the spawn.
+    pub fn replace_world(&mut self, reloaded: GameContext) {
+        let still_fits = !overlaps(self.player.position, self.player.radius, |x, y| {
+            cell_is_solid(&reloaded.world, &reloaded.cell_types, &reloaded.doors, x, y)
+        });
+
+        let previous = std::mem::replace(self, reloaded);
+        if still_fits {
+            self.player = previous.player;
+        }
+    }
+
+    /// Returns the properties of the cell at (`x`, `y`),
+    /// or `None` if it is outside of the world.
+    pub fn cell_properties_at(&self, x: i32, y: i32) -> Option<&CellProperties> {
+        if x < 0 || y < 0 { return None }
+        self.cell_properties.get(x as usize, y as usize)
+    }
+
+    /// Returns the type of a cell in the world, or `None` for empty cells.
+    pub fn cell_type(&self, cell: i32) -> Option<&CellType> {
+        if cell < 1 { return None }
+        self.cell_types.get(cell as usize - 1)
+    }
+
+    /// Returns the height of the tallest wall in the world, relative to a standard wall.
+    pub fn tallest_wall(&self) -> f32 {
+        self.world.iter()
+            .zip(self.cell_properties.iter())
+            .filter(|(cell, _)| **cell != 0)
+            .map(|(_, properties)| properties.wall_height)
+            .fold(0.0, f32::max)
+    }
+
     /// Increment the game state by one tick.
     /// This function is called periodically to advance the game state by one unit of time, known as a "tick." 
     /// 
-    /// At the moment it only updates the player position,
-    /// as that is the only dynamic state.
+    /// At the moment it only updates the player and doors,
+    /// as they are the only dynamic state.
+    /// The intents are kept, so they apply to every tick until `clear_intents` is called.
     pub fn tick(&mut self, dt: Duration) {
         self.handle_player_movement(dt);
+        self.handle_player_vertical_movement(dt);
+
+        let player = &self.player;
+        for (&(x, y), door) in self.doors.iter_mut() {
+            let door_cell = (x as i32, y as i32);
+            let blocked = overlaps(player.position, player.radius, |cell_x, cell_y| (cell_x, cell_y) == door_cell);
+            door.update(dt.as_secs_f32(), blocked);
+        }
+    }
+
+    /// Resets the intents, once all the ticks they apply to have been run.
+    pub fn clear_intents(&mut self) {
+        self.intents = Intents::default()
+    }
+
+    /// Resets the intents which come from held input, before it is read again for the next frame.
+    /// Presses such as jumping are kept, so they aren't lost if no tick runs before the next frame.
+    pub fn release_held_intents(&mut self) {
+        self.intents = Intents {
+            jump: self.intents.jump,
+            ..Intents::default()
+        }
+    }
+
+    /// Opens or closes the nearest door in front of the player, if there is one within reach.
+    pub fn use_door(&mut self) {
+        const REACH: f32 = 1.5;
+        const STEPS: usize = 15;
+
+        for i in 0..=STEPS {
+            let distance = REACH * i as f32 / STEPS as f32;
+            let x = (self.player.position.x + self.player.camera_direction.x * distance) as usize;
+            let y = (self.player.position.y + self.player.camera_direction.y * distance) as usize;
+
+            if let Some(door) = self.doors.get_mut(&(x, y)) {
+                // Don't close the door on top of the player
+                let door_cell = (x as i32, y as i32);
+                let under_player = overlaps(self.player.position, self.player.radius, |cell_x, cell_y| (cell_x, cell_y) == door_cell);
+                if under_player && door.is_passable() { return }
+                door.toggle();
+                return
+            }
+
+            // Doors can't be used through walls
+            if self.world.get(x, y) != Some(&0) { return }
+        }
     }
 
-    /// Enables 'forward' in the move state
+    /// Adds 'forward' to the intents
     pub fn move_forward(&mut self) {
-        self.move_state[0] = true;
+        self.intents.forward += 1.0;
     }
 
-    /// Enables 'backwards' in the move state
+    /// Adds 'backwards' to the intents
     pub fn move_backwards(&mut self) {
-        self.move_state[1] = true;
+        self.intents.forward -= 1.0;
     }
 
-    /// Enables 'left' in the move state
+    /// Adds 'strafe left' to the intents
+    pub fn strafe_left(&mut self) {
+        self.intents.strafe -= 1.0;
+    }
+
+    /// Adds 'strafe right' to the intents
+    pub fn strafe_right(&mut self) {
+        self.intents.strafe += 1.0;
+    }
+
+    /// Adds 'left' to the intents
     pub fn turn_left(&mut self) {
-        self.move_state[2] = true;
+        self.intents.turn += 1.0;
     }
 
-    /// Enables 'left' in the move state
+    /// Adds 'right' to the intents
     pub fn turn_right(&mut self) {
-        self.move_state[3] = true;
+        self.intents.turn -= 1.0;
+    }
+
+    /// Adds 'look up' to the intents
+    pub fn look_up(&mut self) {
+        self.intents.look += 1.0;
+    }
+
+    /// Adds 'look down' to the intents
+    pub fn look_down(&mut self) {
+        self.intents.look -= 1.0;
+    }
+
+    /// Adds analog movement to the intents, such as from a game controller.
+    pub fn steer(&mut self, stick: StickIntents) {
+        self.intents.forward += stick.forward;
+        self.intents.strafe += stick.strafe;
+        self.intents.turn += stick.turn;
+        self.intents.look += stick.look;
+    }
+
+    /// Enables 'jump' in the intents
+    pub fn jump(&mut self) {
+        self.intents.jump = true;
     }
 
-    /// Modifies the player position based on the current move
-    /// state and the current frame time.
+    /// Enables 'crouch' in the intents,
+    /// the player stays crouched for as long as this is called every frame
+    pub fn crouch(&mut self) {
+        self.intents.crouch = true;
+    }
+
+    /// Enables 'sprint' in the intents, which raises the top speed by `Player::sprint_multiplier`
+    pub fn sprint(&mut self) {
+        self.intents.sprint = true;
+    }
+
+    /// Speeds the player up towards the direction they are trying to move in, or slows them down
+    /// if they aren't trying to move, then moves them by their velocity over the tick.
     fn handle_player_movement(&mut self, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        let intents = self.intents;
+        let player = &mut self.player;
 
-        // Calculate a new move and rotation speed based of the current frame time.
-        // Ensures movement speed is consistent with varying framerate.
-        let actual_move_speed = self.player.move_speed * (dt.as_millis() as f32) / 1000.0;
-        let actual_rot_speed = self.player.rot_speed * (dt.as_millis() as f32) / 1000.0;
+        // Strafing moves along the camera plane, which points to the right of the view
+        let direction = player.camera_direction;
+        let right = player.right();
+        let forward = intents.forward.clamp(-1.0, 1.0);
+        let strafe = intents.strafe.clamp(-1.0, 1.0);
+        let mut wish = Vector2::new(
+            direction.x * forward + right.x * strafe,
+            direction.y * forward + right.y * strafe
+        );
 
-        // Create shorthand variables for better readability
-        let pos_x = &mut self.player.position.x;
-        let pos_y = &mut self.player.position.y;
+        // Moving diagonally mustn't be faster than moving straight
+        let wish_length = (wish.x * wish.x + wish.y * wish.y).sqrt();
+        if wish_length > 1.0 {
+            wish = Vector2::new(wish.x / wish_length, wish.y / wish_length);
+        }
 
-        let dir_x = &mut self.player.camera_direction.x;
-        let dir_y = &mut self.player.camera_direction.y;
+        let top_speed = player.move_speed * if intents.sprint { player.sprint_multiplier } else { 1.0 };
+        let target = Vector2::new(wish.x * top_speed, wish.y * top_speed);
 
-        let plane_x = &mut self.player.camera_plane.x;
-        let plane_y = &mut self.player.camera_plane.y;
+        // Friction only slows the player down on the ground, so they keep their speed through a jump
+        let rate = if wish_length > 0.0 {
+            player.acceleration
+        } else if player.on_ground() {
+            player.friction
+        } else {
+            0.0
+        };
+        player.velocity = move_towards(player.velocity, target, rate * dt);
 
-        // Credit to [Lode's Computer Graphics Tutorial](https://lodev.org/cgtutor/raycasting.html)
-        // for movement code
+        if player.velocity.x != 0.0 || player.velocity.y != 0.0 {
+            let start = player.position;
+            let motion = Vector2::new(player.velocity.x * dt, player.velocity.y * dt);
+            let is_solid = |x, y| cell_is_solid(&self.world, &self.cell_types, &self.doors, x, y);
+            player.position = move_circle(start, player.radius, motion, is_solid);
 
-        // if Forward==true
-        if self.move_state[0] {
-            if self.world[(*pos_x + *dir_x * actual_move_speed) as usize][*pos_y as usize] == 0 {
-                *pos_x += *dir_x * actual_move_speed
-            }
-            if self.world[*pos_x as usize][(*pos_y + *dir_y * actual_move_speed) as usize] == 0 {
-                *pos_y += *dir_y * actual_move_speed
+            // Walls take away the part of the velocity going into them, so the player doesn't stick to them
+            if dt > 0.0 {
+                player.velocity = Vector2::new((player.position.x - start.x) / dt, (player.position.y - start.y) / dt);
             }
         }
 
-        // if Backwards==true
-        if self.move_state[1] {
-            if self.world[(*pos_x - *dir_x * actual_move_speed) as usize][*pos_y as usize] == 0 {
-                *pos_x -= *dir_x * actual_move_speed
-            }
-            if self.world[*pos_x as usize][(*pos_y - *dir_y * actual_move_speed) as usize] == 0 {
-                *pos_y -= *dir_y * actual_move_speed
-            }
+        let turn = intents.turn.clamp(-1.0, 1.0);
+        if turn != 0.0 {
+            player.rotate(turn * player.rot_speed * dt);
         }
+    }
 
-        let mut prev_dir_x: f32;
-        let mut prev_plane_x: f32;
-
-        // if Left==true
-        if self.move_state[2] {
-            prev_dir_x = *dir_x;
-            *dir_x = *dir_x * actual_rot_speed.cos() - *dir_y * actual_rot_speed.sin();
-            *dir_y = prev_dir_x * actual_rot_speed.sin() + *dir_y * actual_rot_speed.cos();
-            prev_plane_x = *plane_x;
-            *plane_x = *plane_x * actual_rot_speed.cos() - *plane_y * actual_rot_speed.sin();
-            *plane_y = prev_plane_x * actual_rot_speed.sin() + *plane_y * actual_rot_speed.cos();
+    /// Modifies the player pitch, jump and crouch based on the
+    /// current intents and the tick length.
+    fn handle_player_vertical_movement(&mut self, dt: Duration) {
+
+        let dt = dt.as_secs_f32();
+        let player = &mut self.player;
+
+        let look = self.intents.look.clamp(-1.0, 1.0);
+        if look != 0.0 {
+            player.tilt(look * player.pitch_speed * dt);
         }
 
-        // if Right==true
-        if self.move_state[3] {
-            prev_dir_x = *dir_x;
-            *dir_x = *dir_x * (-actual_rot_speed).cos() - *dir_y * (-actual_rot_speed).sin();
-            *dir_y = prev_dir_x * (-actual_rot_speed).sin() + *dir_y * (-actual_rot_speed).cos();
-            prev_plane_x = *plane_x;
-            *plane_x = *plane_x * (-actual_rot_speed).cos() - *plane_y * (-actual_rot_speed).sin();
-            *plane_y = prev_plane_x * (-actual_rot_speed).sin() + *plane_y * (-actual_rot_speed).cos();
+        // Jumping is only possible from the floor
+        if self.intents.jump && player.on_ground() {
+            player.vertical_velocity = player.jump_speed;
         }
 
-        // Once we have processed all the movements, reset the move state
-        self.move_state = [false; 4]
+        player.crouching = self.intents.crouch;
+
+        // Apply gravity while in the air
+        if !player.on_ground() || player.vertical_velocity > 0.0 {
+            player.vertical_position += player.vertical_velocity * dt;
+            player.vertical_velocity -= player.gravity * dt;
+
+            // Landed
+            if player.vertical_position <= 0.0 {
+                player.vertical_position = 0.0;
+                player.vertical_velocity = 0.0;
+            }
+        }
+    }
+}
+
+/// Returns true if the cell at (`x`, `y`) is empty, isn't solid or holds an open door.
+/// Anything outside of the world, or with an unknown cell type, is solid.
+fn cell_is_passable(world: &Grid<i32>, cell_types: &[CellType], doors: &HashMap<(usize, usize), Door>, x: usize, y: usize) -> bool {
+    match (doors.get(&(x, y)), world.get(x, y)) {
+        (Some(door), _) => door.is_passable(),
+        (None, Some(&0)) => true,
+        // Cells which aren't a known cell type are treated as solid
+        (None, Some(&cell)) => usize::try_from(cell - 1).ok()
+            .and_then(|index| cell_types.get(index))
+            .is_some_and(|cell_type| !cell_type.solid),
+        (None, None) => false
+    }
+}
+
+/// Returns true if the player can't stand in any part of the cell at (`x`, `y`), as used for collision.
+fn cell_is_solid(world: &Grid<i32>, cell_types: &[CellType], doors: &HashMap<(usize, usize), Door>, x: i32, y: i32) -> bool {
+    x < 0 || y < 0 || !cell_is_passable(world, cell_types, doors, x as usize, y as usize)
+}
+
+/// Moves `from` towards `to` by at most `max_change`, without going past it.
+fn move_towards(from: Vector2<f32>, to: Vector2<f32>, max_change: f32) -> Vector2<f32> {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    let distance = (dx * dx + dy * dy).sqrt();
 
+    if distance <= max_change || distance == 0.0 {
+        to
+    } else {
+        Vector2::new(from.x + dx / distance * max_change, from.y + dy / distance * max_change)
     }
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+//! Contains the grid used to store the world and its cell properties.
+
+use std::{collections::VecDeque, ops::{Index, IndexMut}};
+
+/// A rectangular grid of cells stored on the heap.
+///
+/// Indexed as `grid[x][y]`, where `width` is the number of cells along x
+/// and `height` is the number of cells along y.
+#[derive(Clone)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    // Stored one x at a time, so `grid[x]` is a contiguous slice
+    cells: Vec<T>
+}
+
+impl<T: Clone> Grid<T> {
+    /// Creates a `width` by `height` grid with every cell set to `value`.
+    pub fn new(width: usize, height: usize, value: T) -> Grid<T> {
+        Grid {
+            width,
+            height,
+            cells: vec![value; width * height]
+        }
+    }
+}
+
+impl<T> Grid<T> {
+    /// Builds a grid from a list of rows, one for each x.
+    /// Returns `None` if the rows aren't all the same length.
+    pub fn from_rows(rows: Vec<Vec<T>>) -> Option<Grid<T>> {
+        let width = rows.len();
+        let height = rows.first().map_or(0, |row| row.len());
+
+        if rows.iter().any(|row| row.len() != height) { return None }
+
+        Some(Grid {
+            width,
+            height,
+            cells: rows.into_iter().flatten().collect()
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the cell at (`x`, `y`), or `None` if it is outside of the grid.
+    pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        if x >= self.width || y >= self.height { return None }
+        Some(&self.cells[x * self.height + y])
+    }
+
+    /// Returns true if (`x`, `y`) is one of the outermost cells of the grid.
+    pub fn on_boundary(&self, x: usize, y: usize) -> bool {
+        x == 0 || y == 0 || x + 1 == self.width || y + 1 == self.height
+    }
+
+    /// Iterates over the rows of the grid, one for each x.
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> {
+        // Always yields `width` rows, even if they are empty
+        (0..self.width).map(|x| &self[x])
+    }
+
+    /// Iterates over every cell, in the same order as `rows`.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.cells.iter()
+    }
+
+    /// Returns every cell which can be reached from `start` by stepping north, south, east or west
+    /// onto cells where `passable` is true, ordered by the number of steps from `start`.
+    /// `start` is always included if it is inside the grid.
+    pub fn flood_fill(&self, start: (usize, usize), passable: impl Fn(usize, usize, &T) -> bool) -> Vec<(usize, usize)> {
+        if self.get(start.0, start.1).is_none() { return Vec::new() }
+
+        let mut visited = vec![false; self.cells.len()];
+        let mut queue = VecDeque::from([start]);
+        let mut reached = Vec::new();
+
+        visited[start.0 * self.height + start.1] = true;
+
+        while let Some((x, y)) = queue.pop_front() {
+            reached.push((x, y));
+
+            for (next_x, next_y) in [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)] {
+                let Some(cell) = self.get(next_x, next_y) else { continue };
+                let index = next_x * self.height + next_y;
+
+                if !visited[index] && passable(next_x, next_y, cell) {
+                    visited[index] = true;
+                    queue.push_back((next_x, next_y));
+                }
+            }
+        }
+
+        reached
+    }
+}
+
+impl<T> Index<usize> for Grid<T> {
+    type Output = [T];
+
+    fn index(&self, x: usize) -> &[T] {
+        &self.cells[x * self.height..(x + 1) * self.height]
+    }
+}
+
+impl<T> IndexMut<usize> for Grid<T> {
+    fn index_mut(&mut self, x: usize) -> &mut [T] {
+        &mut self.cells[x * self.height..(x + 1) * self.height]
+    }
+}
\ No newline at end of file
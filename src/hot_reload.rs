@@ -0,0 +1,81 @@
+//! Contains the file watcher used to reload maps and textures while the game is running.
+//!
+//! Files are polled for changes to their modification time, which works the same
+//! on every platform and needs no extra dependencies.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime}
+};
+
+/// How often the watched files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A file and the modification time it had when it was last checked.
+struct WatchedFile {
+    path: PathBuf,
+    /// `None` if the file couldn't be read, so it is picked up once it exists again
+    modified: Option<SystemTime>
+}
+
+/// Keeps track of a set of files and reports which of them have changed on disk.
+pub struct FileWatcher {
+    files: Vec<WatchedFile>,
+    last_poll: Instant
+}
+
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        FileWatcher {
+            files: Vec::new(),
+            last_poll: Instant::now()
+        }
+    }
+
+    /// Starts watching `path`, changes made before this are ignored.
+    pub fn watch(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        if self.files.iter().any(|file| file.path == path) { return }
+
+        let modified = modified_time(&path);
+        self.files.push(WatchedFile { path, modified });
+    }
+
+    /// Stops watching every file.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+
+    /// Treats the current version of `path` as already seen,
+    /// such as after the game has written the file itself.
+    pub fn mark_seen(&mut self, path: &Path) {
+        for file in self.files.iter_mut().filter(|file| file.path == path) {
+            file.modified = modified_time(&file.path);
+        }
+    }
+
+    /// Returns the files which have changed since the last call.
+    /// Files are only checked every `POLL_INTERVAL`, so this is cheap to call every frame.
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL { return Vec::new() }
+        self.last_poll = Instant::now();
+
+        let mut changed = Vec::new();
+
+        for file in &mut self.files {
+            let modified = modified_time(&file.path);
+            // A file which has gone missing is left alone until it comes back,
+            // as editors often delete a file before writing the new version
+            if modified.is_some() && modified != file.modified {
+                changed.push(file.path.clone());
+            }
+            file.modified = modified;
+        }
+
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
\ No newline at end of file
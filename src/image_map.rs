@@ -0,0 +1,107 @@
+//! Contains the import of world layouts from PNG images.
+//!
+//! Each pixel of the image is one cell of the world, with the rows of the image
+//! going along x and the columns along y, the same way round as the minimap.
+//! A `Palette` decides what each colour means. The alpha channel is ignored.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    Error,
+    grid::Grid,
+    sprite::Sprite,
+    util::Vector2
+};
+
+/// What a colour in a layout image turns into.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PaletteEntry {
+    /// A cell with this value, 0 is an empty cell
+    Cell(i32),
+    /// Empty cell the player starts in, there can only be one
+    Spawn,
+    /// Empty cell with a sprite using this texture id in its centre
+    Sprite(i32)
+}
+
+/// Table of RGB colours, such as `0xff0000`, and what they mean in a layout image.
+#[derive(Clone)]
+pub struct Palette {
+    pub entries: HashMap<u32, PaletteEntry>
+}
+
+impl Palette {
+    /// Creates a palette with no colours in it.
+    pub fn empty() -> Palette {
+        Palette { entries: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, colour: u32, entry: PaletteEntry) {
+        self.entries.insert(colour, entry);
+    }
+}
+
+impl Default for Palette {
+    /// Black is empty, orange is the spawn and white is wall 1.
+    /// Walls 1 to 6 also use the same colours as the minimap.
+    fn default() -> Palette {
+        let mut palette = Palette::empty();
+
+        palette.insert(0x000000, PaletteEntry::Cell(0));
+        palette.insert(0xff8000, PaletteEntry::Spawn);
+        palette.insert(0xffffff, PaletteEntry::Cell(1));
+
+        for (i, colour) in [0xff0000, 0x00ff00, 0x0000ff, 0xffff00, 0x00ffff, 0xff00ff].into_iter().enumerate() {
+            palette.insert(colour, PaletteEntry::Cell(i as i32 + 1));
+        }
+
+        palette
+    }
+}
+
+/// The parts of a world which were read from a layout image.
+pub struct ImageLayout {
+    pub world: Grid<i32>,
+    /// Centre of the spawn cell, if the image has one
+    pub spawn: Option<Vector2<f32>>,
+    pub sprites: Vec<Sprite>
+}
+
+/// Reads a world layout from the PNG image at `path`, using `palette` to turn colours into cells.
+pub fn load_layout(path: impl AsRef<Path>, palette: &Palette) -> Result<ImageLayout, Error> {
+    let path = path.as_ref();
+    let image = image::open(path)
+        .map_err(|err| format!("IMAGE - Could not read '{}' - {err}", path.display()))?
+        .to_rgb8();
+
+    let (columns, rows) = image.dimensions();
+
+    let mut world = Grid::new(rows as usize, columns as usize, 0);
+    let mut spawn = None;
+    let mut sprites = Vec::new();
+
+    for (column, row, pixel) in image.enumerate_pixels() {
+        let [r, g, b] = pixel.0;
+        let colour = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        let (x, y) = (row as usize, column as usize);
+
+        let Some(entry) = palette.entries.get(&colour) else {
+            return Err(format!("IMAGE - '{}' pixel ({column}, {row}) has colour {colour:06x}, which is not in the palette", path.display()).into())
+        };
+
+        let centre = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+
+        match *entry {
+            PaletteEntry::Cell(cell) => world[x][y] = cell,
+            PaletteEntry::Spawn => {
+                if spawn.is_some() {
+                    return Err(format!("IMAGE - '{}' pixel ({column}, {row}) is a second spawn point", path.display()).into())
+                }
+                spawn = Some(centre);
+            }
+            PaletteEntry::Sprite(texture) => sprites.push(Sprite::new(centre.x, centre.y, texture))
+        }
+    }
+
+    Ok(ImageLayout { world, spawn, sprites })
+}
\ No newline at end of file
@@ -0,0 +1,229 @@
+//! Contains the settings which turn raw input into player movement.
+
+/// How far the view turns for each pixel the mouse moves, in radians
+const DEFAULT_MOUSE_SENSITIVITY: f32 = 0.003;
+
+/// Settings for looking around with the mouse.
+#[derive(Clone, Copy)]
+pub struct MouseLook {
+    /// How far the view turns for each pixel the mouse moves.
+    /// Turning is in radians and looking up and down is in fractions of the screen height.
+    pub sensitivity: f32,
+    /// Look down when the mouse moves up, like a flight stick
+    pub invert: bool
+}
+
+impl Default for MouseLook {
+    fn default() -> Self {
+        Self {
+            sensitivity: DEFAULT_MOUSE_SENSITIVITY,
+            invert: false
+        }
+    }
+}
+
+impl MouseLook {
+    /// Returns how far to turn and tilt the view for the mouse moving by (`dx`, `dy`) pixels,
+    /// as used by `Player::rotate` and `Player::tilt`.
+    pub fn look(&self, dx: i32, dy: i32) -> (f32, f32) {
+        // Moving the mouse right turns right, which is a negative angle
+        let turn = -dx as f32 * self.sensitivity;
+        // Screen y points down, so moving the mouse up gives a negative `dy`
+        let tilt = -dy as f32 * self.sensitivity;
+
+        (turn, if self.invert { -tilt } else { tilt })
+    }
+}
+
+/// Largest value of a raw SDL controller axis
+const AXIS_MAX: f32 = 32767.0;
+
+/// Turns a raw SDL controller axis value into -1.0 to 1.0.
+pub fn axis_value(raw: i16) -> f32 {
+    (raw as f32 / AXIS_MAX).clamp(-1.0, 1.0)
+}
+
+/// How the position of an analog stick is turned into movement.
+#[derive(Clone, Copy)]
+pub struct StickResponse {
+    /// Distance from the centre the stick has to move before it does anything,
+    /// so a worn stick which doesn't centre properly doesn't drift
+    pub deadzone: f32,
+    /// The stick distance past the deadzone is raised to this power.
+    /// 1.0 is linear, and higher values give finer control near the centre.
+    pub curve: f32
+}
+
+impl StickResponse {
+    /// Returns the (x, y) position of a stick after the deadzone and curve, each from -1.0 to 1.0.
+    /// The deadzone is round, so it doesn't snap diagonal movement onto the axes.
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let distance = (x * x + y * y).sqrt();
+        if distance <= self.deadzone { return (0.0, 0.0) }
+
+        let strength = ((distance - self.deadzone) / (1.0 - self.deadzone)).min(1.0).powf(self.curve);
+        (x / distance * strength, y / distance * strength)
+    }
+}
+
+/// Positions of the sticks of a game controller, each axis from -1.0 to 1.0.
+/// Down and right are positive, as they are in SDL.
+#[derive(Clone, Copy, Default)]
+pub struct Sticks {
+    pub left_x: f32,
+    pub left_y: f32,
+    pub right_x: f32,
+    pub right_y: f32
+}
+
+/// Movement asked for by a game controller, each from -1.0 to 1.0 as used by `GameContext::steer`.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct StickIntents {
+    pub forward: f32,
+    pub strafe: f32,
+    pub turn: f32,
+    pub look: f32
+}
+
+/// Settings for playing with a game controller.
+/// The left stick moves and strafes, and the right stick looks around.
+#[derive(Clone, Copy)]
+pub struct GamepadSettings {
+    pub move_response: StickResponse,
+    pub look_response: StickResponse,
+    /// Look down when the right stick is pushed up, like a flight stick
+    pub invert: bool
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            move_response: StickResponse { deadzone: 0.2, curve: 1.0 },
+            // Aiming needs more precision near the centre than walking does
+            look_response: StickResponse { deadzone: 0.2, curve: 2.0 },
+            invert: false
+        }
+    }
+}
+
+impl GamepadSettings {
+    /// Returns the movement asked for by sticks held at `sticks`.
+    pub fn intents(&self, sticks: &Sticks) -> StickIntents {
+        let (move_x, move_y) = self.move_response.apply(sticks.left_x, sticks.left_y);
+        let (look_x, look_y) = self.look_response.apply(sticks.right_x, sticks.right_y);
+
+        // Stick y points down, so pushing a stick up gives a negative value
+        StickIntents {
+            forward: -move_y,
+            strafe: move_x,
+            // Pushing right turns right, which is a negative turn
+            turn: -look_x,
+            look: if self.invert { look_y } else { -look_y }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < EPSILON, "Expected {expected}, found {actual}");
+    }
+
+    #[test]
+    fn axis_value_covers_minus_one_to_one() {
+        assert_eq!(axis_value(0), 0.0);
+        assert_eq!(axis_value(i16::MAX), 1.0);
+        // The negative range is one step longer than the positive range
+        assert_eq!(axis_value(i16::MIN), -1.0);
+        assert_close(axis_value(16384), 16384.0 / 32767.0);
+    }
+
+    #[test]
+    fn stick_inside_deadzone_does_nothing() {
+        let response = StickResponse { deadzone: 0.2, curve: 1.0 };
+        assert_eq!(response.apply(0.0, 0.0), (0.0, 0.0));
+        assert_eq!(response.apply(0.15, -0.1), (0.0, 0.0));
+        assert_eq!(response.apply(0.0, 0.2), (0.0, 0.0));
+    }
+
+    #[test]
+    fn stick_outside_deadzone_is_rescaled_from_zero() {
+        let response = StickResponse { deadzone: 0.2, curve: 1.0 };
+        let (x, y) = response.apply(0.6, 0.0);
+        assert_close(x, 0.5);
+        assert_close(y, 0.0);
+
+        let (x, y) = response.apply(0.0, -0.6);
+        assert_close(x, 0.0);
+        assert_close(y, -0.5);
+    }
+
+    #[test]
+    fn deadzone_is_round_on_diagonals() {
+        let response = StickResponse { deadzone: 0.2, curve: 1.0 };
+        // Each axis is past the deadzone on its own, but the stick is only 0.17 from the centre
+        assert_eq!(response.apply(0.12, 0.12), (0.0, 0.0));
+
+        // Diagonal movement keeps its direction
+        let (x, y) = response.apply(0.5, 0.5);
+        assert!(x > 0.0);
+        assert_close(x, y);
+    }
+
+    #[test]
+    fn curve_raises_the_distance_past_the_deadzone_to_its_power() {
+        let response = StickResponse { deadzone: 0.2, curve: 2.0 };
+        let (x, _) = response.apply(0.6, 0.0);
+        assert_close(x, 0.25);
+
+        let response = StickResponse { deadzone: 0.0, curve: 3.0 };
+        let (x, _) = response.apply(0.5, 0.0);
+        assert_close(x, 0.125);
+    }
+
+    #[test]
+    fn full_deflection_is_clamped_to_one() {
+        let response = StickResponse { deadzone: 0.2, curve: 2.0 };
+        let (x, y) = response.apply(1.0, 0.0);
+        assert_close(x, 1.0);
+        assert_close(y, 0.0);
+
+        // Pushed into a corner the stick reads past 1.0 from the centre
+        let (x, y) = response.apply(1.0, 1.0);
+        assert_close((x * x + y * y).sqrt(), 1.0);
+    }
+
+    #[test]
+    fn sticks_follow_the_game_sign_conventions() {
+        let settings = GamepadSettings::default();
+
+        // Left stick up and right moves forwards and strafes right
+        let intents = settings.intents(&Sticks { left_x: 1.0, left_y: -1.0, ..Sticks::default() });
+        assert!(intents.forward > 0.0);
+        assert!(intents.strafe > 0.0);
+        assert_eq!((intents.turn, intents.look), (0.0, 0.0));
+
+        // Right stick right and up turns right and looks up
+        let intents = settings.intents(&Sticks { right_x: 1.0, right_y: -1.0, ..Sticks::default() });
+        assert!(intents.turn < 0.0);
+        assert!(intents.look > 0.0);
+        assert_eq!((intents.forward, intents.strafe), (0.0, 0.0));
+
+        assert_eq!(settings.intents(&Sticks::default()), StickIntents::default());
+    }
+
+    #[test]
+    fn invert_flips_looking_up_and_down_only() {
+        let sticks = Sticks { left_y: -1.0, right_x: 1.0, right_y: -1.0, ..Sticks::default() };
+        let normal = GamepadSettings::default().intents(&sticks);
+        let inverted = GamepadSettings { invert: true, ..GamepadSettings::default() }.intents(&sticks);
+
+        assert_close(inverted.look, -normal.look);
+        assert_close(inverted.turn, normal.turn);
+        assert_close(inverted.forward, normal.forward);
+    }
+}
\ No newline at end of file
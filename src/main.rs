@@ -1,28 +1,170 @@
 mod texture;
+mod cave;
+mod cell;
+mod collision;
 mod player;
 mod engine;
 mod render;
+mod raycast;
 mod game;
+mod grid;
+mod hot_reload;
+mod image_map;
+mod input;
+mod door;
+mod editor;
+mod dungeon;
+mod fog;
+mod map;
+mod maze;
+mod sprite;
 mod util;
+mod validate;
 
 type Error = Box<dyn std::error::Error>;
 
+use std::path::PathBuf;
+
+use cave::CaveOptions;
+use dungeon::DungeonOptions;
 use engine::Engine;
+use game::GameContext;
+use image_map::Palette;
+use map::{Map, DEFAULT_MAP};
+use maze::MazeOptions;
+use render::Renderer;
 
 fn main() -> Result<(), Error> {
 
     // TODO:
     // Make code more robust in general
     // (how do i cast numeric types in a non horrendously ugly way)
-    // Collision is frame-rate dependant, completely breaks at fps < 5
-    // Add mouse movement/strafing (DOOM music starts to play)
     // Add immediate mode GUI
 
-    let mut system = Engine::new("3D Raycaster", 900, 600)
+    let args: Vec<String> = std::env::args().collect();
+
+    // `--map <file.map>` plays a map other than the default one,
+    // `--image <layout.png>` plays a layout image with the default palette and textures,
+    // `--generate maze|dungeon|cave` plays a generated level, see `generate_map`.
+    // Only maps loaded from a map file are saved back to it by the editor,
+    // and not ones whose cells are read from an `[image]`.
+    let (game_context, map_path) = if let Some(path) = flag_value(&args, "--map", "<file.map>")? {
+        (GameContext::load(path)?, Some(PathBuf::from(path)))
+    } else if let Some(path) = flag_value(&args, "--image", "<layout.png>")? {
+        let textures = Map::load(DEFAULT_MAP)?.textures;
+        let map = Map::from_image(path, &Palette::default(), textures)?;
+        (GameContext::from_map(&map)?, None)
+    } else if let Some(kind) = flag_value(&args, "--generate", "<maze|dungeon|cave>")? {
+        (GameContext::from_map(&generate_map(&args, kind)?)?, None)
+    } else {
+        (GameContext::new()?, Some(PathBuf::from(DEFAULT_MAP)))
+    };
+
+    // `--headless <file.png>` renders a single frame without opening a window
+    if let Some(output) = flag_value(&args, "--headless", "<output.png>")? {
+        return render_headless_frame(game_context, output, 900, 600);
+    }
+
+    let mut system = Engine::new("3D Raycaster", 900, 600, game_context)
         .expect("Failed to initialise engine");
 
+    if let Some(path) = map_path {
+        system.set_map_path(path);
+    }
+    // `--save-as <file.map>` saves the editor's changes to another file, such as for maps drawn as an image
+    if let Some(path) = flag_value(&args, "--save-as", "<file.map>")? {
+        system.set_save_path(PathBuf::from(path));
+    }
     system.set_target_fps(120);
+
+    // `--sensitivity <n>` sets how fast the mouse turns the view, `--invert-mouse` flips looking up and down
+    if let Some(sensitivity) = flag_value(&args, "--sensitivity", "<number>")? {
+        let sensitivity = sensitivity.parse().map_err(|_| format!("Expected a number for --sensitivity, found '{sensitivity}'"))?;
+        system.set_mouse_sensitivity(sensitivity);
+    }
+    system.set_invert_mouse(args.iter().any(|arg| arg == "--invert-mouse"));
+
     system.main_loop()?;
 
+    Ok(())
+}
+
+/// Returns the value after `flag` in the command line arguments, if the flag was given.
+fn flag_value<'a>(args: &'a [String], flag: &str, usage: &str) -> Result<Option<&'a str>, Error> {
+    let Some(index) = args.iter().position(|arg| arg == flag) else { return Ok(None) };
+
+    match args.get(index + 1) {
+        Some(value) => Ok(Some(value)),
+        None => Err(format!("Usage: {flag} {usage}").into())
+    }
+}
+
+/// Generates a level of the given kind.
+/// `--seed <n>` picks the level, otherwise a new one is made each run,
+/// `--size <w>x<h>` sets the size, in passages for a maze and in cells otherwise,
+/// and `--braid <0-1>` sets the share of dead ends removed from a maze.
+fn generate_map(args: &[String], kind: &str) -> Result<Map, Error> {
+    let seed = match flag_value(args, "--seed", "<number>")? {
+        Some(seed) => seed.parse().map_err(|_| format!("Expected a whole number for --seed, found '{seed}'"))?,
+        None => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_nanos() as u64
+    };
+
+    let size = match flag_value(args, "--size", "<width>x<height>")? {
+        Some(size) => {
+            let parsed = size.split_once('x')
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+            Some(parsed.ok_or_else(|| format!("Expected '<width>x<height>' for --size, found '{size}'"))?)
+        }
+        None => None
+    };
+
+    let map = match kind {
+        "maze" => {
+            let mut options = MazeOptions { seed, ..MazeOptions::default() };
+            if let Some((width, height)) = size {
+                options.width = width;
+                options.height = height;
+            }
+            if let Some(braid) = flag_value(args, "--braid", "<0-1>")? {
+                options.braid = braid.parse().map_err(|_| format!("Expected a number for --braid, found '{braid}'"))?;
+            }
+            maze::generate(&options)
+        }
+        "dungeon" => {
+            let mut options = DungeonOptions { seed, ..DungeonOptions::default() };
+            if let Some((width, height)) = size {
+                options.width = width;
+                options.height = height;
+            }
+            dungeon::generate(&options)
+        }
+        "cave" => {
+            let mut options = CaveOptions { seed, ..CaveOptions::default() };
+            if let Some((width, height)) = size {
+                options.width = width;
+                options.height = height;
+            }
+            cave::generate(&options)
+        }
+        _ => return Err(format!("Unknown level generator '{kind}', expected 'maze', 'dungeon' or 'cave'").into())
+    };
+
+    println!("Generated {kind} with seed {seed}");
+
+    Ok(map)
+}
+
+/// Draws the starting view of the world offscreen and saves it as a PNG.
+fn render_headless_frame(game_context: GameContext, output: &str, width: u32, height: u32) -> Result<(), Error> {
+    let mut renderer = Renderer::new_headless(width, height, &game_context)?;
+
+    renderer.render_context.fog = game_context.metadata.fog;
+    renderer.set_sky(game_context.metadata.sky.as_ref());
+
+    renderer.draw(&game_context)?;
+    renderer.save_frame_png(output)?;
+
+    println!("Saved {width}x{height} frame to '{output}'");
+
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,887 @@
+//! Contains the map file format and its parser.
+//!
+//! Map files are plain text and split into sections, each starting with a `[name]` header.
+//! Everything after a `#` is a comment, and blank lines are ignored.
+//! Numbers are separated by whitespace.
+//!
+//! ```text
+//! [meta]
+//! name = Starting dungeon          # Optional, shown to the player
+//! author = Someone                 # Optional
+//! fog = 181818ff 4 22 exponential 2.5
+//!                                  # Optional, RGBA colour, start and end distance,
+//!                                  # then either `linear` or `exponential <density>`
+//! sky = SKY.png open_cells         # Optional, image in `/img/`, then `everywhere` or `open_cells`
+//!
+//! [textures]
+//! 1 = DUNGEONBRICKS.png            # Texture ids must count up from 1,
+//! 2 = DUNGEONCELL.png              # images are loaded from `/img/`
+//!
+//! [types]                          # Cell types, which the values in the world refer to.
+//! 3 = 1 1 2 2 solid                # Every texture id is also a solid cell type with that
+//! 4 = 2 solid transparent          # texture on each face, unless replaced here.
+//!                                  # <id> = <texture> or <north> <south> <east> <west>,
+//!                                  # then the flags. `solid` blocks the player and
+//!                                  # `transparent` lets rays continue through the cell.
+//!                                  # New ids must count up from the last one.
+//!
+//! [spawn]
+//! position = 22 12                 # x y
+//! direction = -1 0                 # Optional, x y, defaults to -1 0
+//! exit = 1 1                       # Optional, x y of the cell the player is trying to reach
+//!
+//! [cells]                          # Or use an `[image]` instead
+//! 1 1 1 1                          # One line per x, one number per y.
+//! 1 0 0 1                          # 0 is an empty cell, anything else is
+//! 1 0 0 1                          # the cell type of the wall in that cell.
+//! 1 1 1 1                          # The world can be any size, but every line
+//!                                  # must have the same number of cells.
+//!
+//! [properties]
+//! floor 1 1 2 2 = 1                # <property> <x> <y> [<to x> <to y>] [= <value>]
+//! ceiling 1 1 = 2                  # Applies to every cell in the rectangle, inclusive.
+//! height 0 0 0 3 = 0.5             # `floor`, `ceiling` and `height` take a value,
+//! open_sky 1 1 2 2                 # `open_sky` takes none.
+//!
+//! [doors]
+//! 0 1                              # x y, the door uses the texture of its cell
+//!
+//! [sprites]
+//! 1.5 1.5 4                        # x y texture
+//!
+//! [image]
+//! file = layout.png                # Cells are read from a PNG, relative to the map file.
+//! 000000 = 0                       # RGB colour = cell id, `spawn` or `sprite <texture>`.
+//! ffffff = 1                       # See `image_map` for how the image is laid out.
+//! ff8000 = spawn                   # Without any colours the default `Palette` is used.
+//! 808080 = sprite 4
+//! ```
+//!
+//! Either `[cells]` or `[image]` is required, as is a spawn position,
+//! which can come from `[spawn]` or the image. The rest is optional.
+//! `[textures]` must come before `[properties]`, so texture ids can be checked.
+//! North is towards -x and west is towards -y, see `cell::Face`.
+//! Errors point at the file, line and column they were found at.
+
+use std::{fmt::Write, path::{Path, PathBuf}};
+
+use crate::{
+    Error,
+    fog::{Fog, FogFalloff},
+    cell::CellType,
+    game::{CellProperties, WorldItem},
+    grid::Grid,
+    image_map::{self, Palette, PaletteEntry},
+    render::SkyMode,
+    sprite::Sprite,
+    util::Vector2
+};
+
+/// Map loaded when no other map is chosen, relative to the working directory.
+pub const DEFAULT_MAP: &str = "maps/default.map";
+
+/// Information about a map which doesn't affect its layout.
+#[derive(Clone, Default)]
+pub struct Metadata {
+    pub name: String,
+    pub author: String,
+    pub fog: Option<Fog>,
+    /// Sky texture in the `/img/` directory, and where it is drawn
+    pub sky: Option<(String, SkyMode)>
+}
+
+/// Everything needed to build a world, read from a map file.
+///
+/// Cells and texture ids aren't validated here,
+/// that happens when it is turned into a `GameContext`.
+pub struct Map {
+    pub metadata: Metadata,
+    /// File names of the textures, texture id `n` is at index `n-1`
+    pub textures: Vec<String>,
+    /// Cell type id `n` is at index `n-1`
+    pub cell_types: Vec<CellType>,
+    pub spawn_position: Vector2<f32>,
+    pub spawn_direction: Vector2<f32>,
+    /// Cell the player is trying to reach, if the map has one
+    pub exit: Option<(usize, usize)>,
+    pub world: Grid<i32>,
+    /// Always the same size as `world`
+    pub cell_properties: Grid<CellProperties>,
+    /// Cells which are drawn as sliding doors rather than solid walls
+    pub doors: Vec<(usize, usize)>,
+    pub sprites: Vec<Sprite>,
+    /// Image the cells were read from and the palette used to read it, if the layout came from one
+    pub layout_image: Option<(PathBuf, Palette)>,
+    locations: Locations
+}
+
+/// Line and column, both counting from 1.
+#[derive(Clone, Copy, Default)]
+struct Location {
+    line: usize,
+    column: usize
+}
+
+/// Where each part of the map was read from, so problems found
+/// after parsing can still be pointed at in the file.
+#[derive(Default)]
+struct Locations {
+    file: String,
+    spawn: Location,
+    exit: Location,
+    cells: Vec<Vec<Location>>,
+    doors: Vec<Location>,
+    sprites: Vec<Location>,
+    cell_types: Vec<Location>
+}
+
+impl Map {
+    /// Reads and parses the map file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Map, Error> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("MAP - Could not read '{}' - {err}", path.display()))?;
+
+        Map::parse(&path.display().to_string(), &source)
+    }
+
+    /// Parses the contents of a map file, `file` is used in error messages
+    /// and to find images relative to the map.
+    pub fn parse(file: &str, source: &str) -> Result<Map, Error> {
+        Parser::new(file).parse(source)
+    }
+
+    /// Creates a map with no metadata, doors or sprites, and default cell properties.
+    /// `textures` are the file names for the texture ids, starting at 1,
+    /// and each texture id is also a solid cell type.
+    pub fn new(textures: Vec<String>, world: Grid<i32>, spawn_position: Vector2<f32>) -> Map {
+        Map {
+            metadata: Metadata::default(),
+            cell_types: (1..=textures.len() as i32).map(CellType::uniform).collect(),
+            textures,
+            spawn_position,
+            spawn_direction: Vector2::new(-1.0, 0.0),
+            exit: None,
+            cell_properties: Grid::new(world.width(), world.height(), CellProperties::default()),
+            world,
+            doors: Vec::new(),
+            sprites: Vec::new(),
+            layout_image: None,
+            locations: Locations::default()
+        }
+    }
+
+    /// Builds a map from the layout image at `path`, which must mark the spawn.
+    /// `textures` are the file names for the texture ids, starting at 1.
+    pub fn from_image(path: impl AsRef<Path>, palette: &Palette, textures: Vec<String>) -> Result<Map, Error> {
+        let path = path.as_ref();
+        let layout = image_map::load_layout(path, palette)?;
+
+        let spawn_position = layout.spawn
+            .ok_or_else(|| format!("IMAGE - '{}' has no spawn point", path.display()))?;
+
+        let mut map = Map::new(textures, layout.world, spawn_position);
+        map.sprites = layout.sprites;
+        map.layout_image = Some((path.to_path_buf(), palette.clone()));
+
+        Ok(map)
+    }
+
+    /// Formats an error about part of the map with the location it was read from.
+    pub fn describe_error(&self, item: WorldItem, message: &str) -> String {
+        let locations = &self.locations;
+        let location = match item {
+            WorldItem::Cell(x, y) => locations.cells.get(x).and_then(|row| row.get(y)),
+            WorldItem::Door(i) => locations.doors.get(i),
+            WorldItem::Sprite(i) => locations.sprites.get(i),
+            WorldItem::CellType(id) => locations.cell_types.get((id - 1) as usize),
+            WorldItem::Spawn => Some(&locations.spawn),
+            WorldItem::Exit => Some(&locations.exit),
+            WorldItem::World => None
+        };
+
+        match location {
+            Some(Location { line, column }) if *line > 0 =>
+                format!("MAP - {}:{line}:{column}: {message}", locations.file),
+            _ => format!("WORLD - {message}")
+        }
+    }
+
+    /// Writes the map to `path` in the map file format.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_source())
+            .map_err(|err| format!("MAP - Could not write '{}' - {err}", path.display()).into())
+    }
+
+    /// Formats the map in the map file format, which `parse` reads back into the same map.
+    /// Layouts read from an image are written out as `[cells]`.
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        self.write_source(&mut source).expect("Writing to a String can't fail");
+        source
+    }
+
+    fn write_source(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "[meta]")?;
+        if !self.metadata.name.is_empty() { writeln!(out, "name = {}", self.metadata.name)?; }
+        if !self.metadata.author.is_empty() { writeln!(out, "author = {}", self.metadata.author)?; }
+        if let Some(fog) = &self.metadata.fog {
+            let falloff = match fog.falloff {
+                FogFalloff::Linear => "linear".to_string(),
+                FogFalloff::Exponential(density) => format!("exponential {density}")
+            };
+            writeln!(out, "fog = {:08x} {} {} {falloff}", fog.colour, fog.start, fog.end)?;
+        }
+        if let Some((texture, mode)) = &self.metadata.sky {
+            let mode = match mode {
+                SkyMode::Everywhere => "everywhere",
+                // The sky is only drawn when the map has one, so `Off` never needs writing
+                SkyMode::OpenCells | SkyMode::Off => "open_cells"
+            };
+            writeln!(out, "sky = {texture} {mode}")?;
+        }
+
+        writeln!(out, "\n[textures]")?;
+        for (i, texture) in self.textures.iter().enumerate() {
+            writeln!(out, "{} = {texture}", i + 1)?;
+        }
+
+        writeln!(out, "\n[types]")?;
+        for (i, cell_type) in self.cell_types.iter().enumerate() {
+            let id = i as i32 + 1;
+            // Texture ids are already uniform cell types, unless they were replaced
+            if id <= self.textures.len() as i32 && *cell_type == CellType::uniform(id) { continue }
+
+            let [north, south, east, west] = cell_type.faces;
+            write!(out, "{id} =")?;
+            if cell_type.faces == [north; 4] {
+                write!(out, " {north}")?;
+            } else {
+                write!(out, " {north} {south} {east} {west}")?;
+            }
+            if cell_type.solid { write!(out, " solid")?; }
+            if cell_type.transparent { write!(out, " transparent")?; }
+            writeln!(out)?;
+        }
+
+        writeln!(out, "\n[spawn]")?;
+        writeln!(out, "position = {} {}", self.spawn_position.x, self.spawn_position.y)?;
+        writeln!(out, "direction = {} {}", self.spawn_direction.x, self.spawn_direction.y)?;
+        if let Some((x, y)) = self.exit { writeln!(out, "exit = {x} {y}")?; }
+
+        // Right-aligned so the columns line up
+        let cell_width = self.world.iter().map(|cell| cell.to_string().len()).max().unwrap_or(1) + 1;
+        writeln!(out, "\n[cells]")?;
+        for row in self.world.rows() {
+            let line: String = row.iter().map(|cell| format!("{cell:>cell_width$}")).collect();
+            writeln!(out, "{}", line.get(1..).unwrap_or_default())?;
+        }
+
+        writeln!(out, "\n[properties]")?;
+        let default = CellProperties::default();
+        for (x, row) in self.cell_properties.rows().enumerate() {
+            // Runs of the same value along y are written as one rectangle
+            let mut write_runs = |name: &str, value: &dyn Fn(&CellProperties) -> Option<String>| {
+                let mut y = 0;
+                while y < row.len() {
+                    let Some(text) = value(&row[y]) else { y += 1; continue };
+                    let end = (y..row.len()).take_while(|&end| value(&row[end]).as_ref() == Some(&text)).last().unwrap_or(y);
+
+                    match (end > y, text.is_empty()) {
+                        (false, true) => writeln!(out, "{name} {x} {y}"),
+                        (false, false) => writeln!(out, "{name} {x} {y} = {text}"),
+                        (true, true) => writeln!(out, "{name} {x} {y} {x} {end}"),
+                        (true, false) => writeln!(out, "{name} {x} {y} {x} {end} = {text}")
+                    }?;
+                    y = end + 1;
+                }
+                Ok(())
+            };
+
+            write_runs("floor", &|cell| (cell.floor_texture != default.floor_texture).then(|| cell.floor_texture.to_string()))?;
+            write_runs("ceiling", &|cell| (cell.ceiling_texture != default.ceiling_texture).then(|| cell.ceiling_texture.to_string()))?;
+            write_runs("height", &|cell| (cell.wall_height != default.wall_height).then(|| cell.wall_height.to_string()))?;
+            write_runs("open_sky", &|cell| cell.open_sky.then(String::new))?;
+        }
+
+        writeln!(out, "\n[doors]")?;
+        for (x, y) in &self.doors {
+            writeln!(out, "{x} {y}")?;
+        }
+
+        writeln!(out, "\n[sprites]")?;
+        for sprite in &self.sprites {
+            writeln!(out, "{} {} {}", sprite.position.x, sprite.position.y, sprite.texture)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A piece of a line which isn't whitespace.
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    location: Location
+}
+
+/// Splits a line into tokens, stopping at the start of a comment.
+fn tokenise(line: &str, line_number: usize) -> Vec<Token<'_>> {
+    let line = match line.find('#') {
+        Some(comment) => &line[..comment],
+        None => line
+    };
+
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (column, (i, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((i, column)),
+            (true, Some((start_i, start_column))) => {
+                tokens.push(Token {
+                    text: &line[start_i..i],
+                    location: Location { line: line_number, column: start_column + 1 }
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((start_i, start_column)) = start {
+        tokens.push(Token {
+            text: &line[start_i..],
+            location: Location { line: line_number, column: start_column + 1 }
+        });
+    }
+
+    tokens
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    None,
+    Meta,
+    Textures,
+    Spawn,
+    Cells,
+    Properties,
+    Doors,
+    Sprites,
+    Image,
+    Types
+}
+
+/// A value set by a line in the `[properties]` section.
+#[derive(Clone, Copy)]
+enum Property {
+    Floor(i32),
+    Ceiling(i32),
+    Height(f32),
+    OpenSky
+}
+
+/// A line of the `[properties]` section, applied to a rectangle of cells.
+struct PropertyEntry {
+    location: Location,
+    from: (usize, usize),
+    to: (usize, usize),
+    property: Property
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    section: Section,
+    metadata: Metadata,
+    textures: Vec<String>,
+    spawn_position: Option<Vector2<f32>>,
+    spawn_direction: Vector2<f32>,
+    exit: Option<(usize, usize)>,
+    rows: Vec<Vec<i32>>,
+    // Applied once the size of the world is known
+    properties: Vec<PropertyEntry>,
+    // Added to the cell types made from the textures once they are all known
+    cell_types: Vec<(Location, usize, CellType)>,
+    // Layout image and where its file name was read from
+    image: Option<(Location, String)>,
+    palette: Palette,
+    // Layout image found relative to the map file and the palette it was read with
+    layout_image: Option<(PathBuf, Palette)>,
+    doors: Vec<(usize, usize)>,
+    sprites: Vec<Sprite>,
+    locations: Locations,
+    // Sections which have already been read, each may only appear once
+    seen: Vec<Section>,
+    last_line: usize
+}
+
+impl<'a> Parser<'a> {
+    fn new(file: &'a str) -> Self {
+        Self {
+            file,
+            section: Section::None,
+            metadata: Metadata::default(),
+            textures: Vec::new(),
+            spawn_position: None,
+            spawn_direction: Vector2::new(-1.0, 0.0),
+            exit: None,
+            rows: Vec::new(),
+            properties: Vec::new(),
+            cell_types: Vec::new(),
+            image: None,
+            palette: Palette::empty(),
+            layout_image: None,
+            doors: Vec::new(),
+            sprites: Vec::new(),
+            locations: Locations { file: file.to_string(), ..Default::default() },
+            seen: Vec::new(),
+            last_line: 0
+        }
+    }
+
+    fn error(&self, location: Location, message: impl std::fmt::Display) -> Error {
+        format!("MAP - {}:{}:{}: {message}", self.file, location.line, location.column).into()
+    }
+
+    fn parse(mut self, source: &str) -> Result<Map, Error> {
+        for (i, line) in source.lines().enumerate() {
+            self.last_line = i + 1;
+            let tokens = tokenise(line, i + 1);
+
+            let Some(first) = tokens.first() else { continue };
+
+            if first.text.starts_with('[') {
+                self.parse_header(&tokens)?;
+                continue
+            }
+
+            match self.section {
+                Section::None => return Err(self.error(first.location, "Expected a section header such as '[cells]'")),
+                Section::Meta => self.parse_meta(line, &tokens)?,
+                Section::Textures => self.parse_texture(&tokens)?,
+                Section::Spawn => self.parse_spawn(&tokens)?,
+                Section::Cells => self.parse_cells(&tokens)?,
+                Section::Properties => self.parse_property(&tokens)?,
+                Section::Doors => self.parse_door(&tokens)?,
+                Section::Sprites => self.parse_sprite(&tokens)?,
+                Section::Image => self.parse_image(&tokens)?,
+                Section::Types => self.parse_cell_type(&tokens)?
+            }
+        }
+
+        self.finish()
+    }
+
+    fn parse_header(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let header = tokens[0];
+
+        if tokens.len() > 1 {
+            return Err(self.error(tokens[1].location, "Unexpected text after section header"))
+        }
+
+        let section = match header.text {
+            "[meta]" => Section::Meta,
+            "[textures]" => Section::Textures,
+            "[spawn]" => Section::Spawn,
+            "[cells]" => Section::Cells,
+            "[properties]" => Section::Properties,
+            "[doors]" => Section::Doors,
+            "[sprites]" => Section::Sprites,
+            "[image]" => Section::Image,
+            "[types]" => Section::Types,
+            other => return Err(self.error(header.location, format!("Unknown section '{other}'")))
+        };
+
+        if self.seen.contains(&section) {
+            return Err(self.error(header.location, format!("Section '{}' appears more than once", header.text)))
+        }
+
+        self.seen.push(section);
+        self.section = section;
+        Ok(())
+    }
+
+    /// Splits `key = value` lines, returning the key and the tokens of the value.
+    fn key_value<'t>(&self, tokens: &'t [Token<'t>]) -> Result<(Token<'t>, &'t [Token<'t>]), Error> {
+        match tokens {
+            [key, equals, value @ ..] if equals.text == "=" && !value.is_empty() => Ok((*key, value)),
+            [key, equals] if equals.text == "=" => Err(self.error(equals.location, format!("Expected a value for '{}'", key.text))),
+            [key, ..] => Err(self.error(key.location, "Expected '<key> = <value>'")),
+            [] => unreachable!()
+        }
+    }
+
+    fn parse_meta(&mut self, line: &str, tokens: &[Token]) -> Result<(), Error> {
+        let (key, value) = self.key_value(tokens)?;
+
+        match key.text {
+            // Text values keep their spacing, so take the rest of the line as is
+            "name" | "author" => {
+                let start = line.find('=').unwrap_or(0) + 1;
+                let end = line.find('#').unwrap_or(line.len());
+                let text = line[start..end].trim().to_string();
+
+                if key.text == "name" { self.metadata.name = text } else { self.metadata.author = text }
+            }
+            "fog" => {
+                let [colour, start, end, falloff, rest @ ..] = value else {
+                    return Err(self.error(value[0].location, "Expected '<colour> <start> <end> linear' or '<colour> <start> <end> exponential <density>'"))
+                };
+
+                let colour_value = u32::from_str_radix(colour.text, 16)
+                    .ok()
+                    .filter(|_| colour.text.len() == 8)
+                    .ok_or_else(|| self.error(colour.location, format!("Expected an RGBA hex colour such as '181818ff', found '{}'", colour.text)))?;
+
+                let start_value: f32 = self.number(*start)?;
+                let end_value: f32 = self.number(*end)?;
+
+                if start_value < 0.0 || end_value <= start_value {
+                    return Err(self.error(end.location, "Fog must end further away than it starts"))
+                }
+
+                let falloff = match (falloff.text, rest) {
+                    ("linear", []) => FogFalloff::Linear,
+                    ("exponential", [density]) => FogFalloff::Exponential(self.number(*density)?),
+                    ("linear" | "exponential", _) => return Err(self.error(falloff.location, format!("Wrong number of values for '{}' fog", falloff.text))),
+                    (other, _) => return Err(self.error(falloff.location, format!("Unknown fog falloff '{other}', expected 'linear' or 'exponential'")))
+                };
+
+                self.metadata.fog = Some(Fog::new(colour_value, start_value, end_value, falloff));
+            }
+            "sky" => {
+                let [texture, mode] = value else {
+                    return Err(self.error(value[0].location, "Expected '<image> everywhere' or '<image> open_cells'"))
+                };
+
+                let mode = match mode.text {
+                    "everywhere" => SkyMode::Everywhere,
+                    "open_cells" => SkyMode::OpenCells,
+                    other => return Err(self.error(mode.location, format!("Unknown sky mode '{other}', expected 'everywhere' or 'open_cells'")))
+                };
+
+                self.metadata.sky = Some((texture.text.to_string(), mode));
+            }
+            other => return Err(self.error(key.location, format!("Unknown metadata '{other}'")))
+        }
+
+        Ok(())
+    }
+
+    fn parse_texture(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let (id, value) = self.key_value(tokens)?;
+        let id_value: usize = self.number(id)?;
+
+        // Keeps ids lined up with their index in the texture list
+        if id_value != self.textures.len() + 1 {
+            return Err(self.error(id.location, format!("Expected texture id {}, found {id_value}", self.textures.len() + 1)))
+        }
+
+        if value.len() > 1 {
+            return Err(self.error(value[1].location, "Texture file names can't contain spaces"))
+        }
+
+        self.textures.push(value[0].text.to_string());
+        Ok(())
+    }
+
+    fn parse_spawn(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let (key, value) = self.key_value(tokens)?;
+
+        let [x, y] = value else {
+            return Err(self.error(value[0].location, "Expected '<x> <y>'"))
+        };
+
+        if key.text == "exit" {
+            self.exit = Some((self.number(*x)?, self.number(*y)?));
+            self.locations.exit = x.location;
+            return Ok(())
+        }
+
+        let vector = Vector2::new(self.number(*x)?, self.number(*y)?);
+
+        match key.text {
+            "position" => {
+                self.spawn_position = Some(vector);
+                self.locations.spawn = x.location;
+            }
+            "direction" => {
+                let length = (vector.x * vector.x + vector.y * vector.y).sqrt();
+                if !(length > 0.0 && length.is_finite()) {
+                    return Err(self.error(x.location, "Spawn direction can't be zero"))
+                }
+                self.spawn_direction = Vector2::new(vector.x / length, vector.y / length);
+            }
+            other => return Err(self.error(key.location, format!("Unknown spawn setting '{other}'")))
+        }
+
+        Ok(())
+    }
+
+    fn parse_cells(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let x = self.rows.len();
+
+        // Every row must be as long as the first one
+        if let Some(first) = self.rows.first() {
+            if tokens.len() != first.len() {
+                let location = tokens.get(first.len()).unwrap_or(&tokens[tokens.len() - 1]).location;
+                return Err(self.error(location, format!("Expected {} cells in row {x}, found {}", first.len(), tokens.len())))
+            }
+        }
+
+        let mut row = Vec::with_capacity(tokens.len());
+        for token in tokens {
+            row.push(self.number(*token)?);
+        }
+
+        self.rows.push(row);
+        self.locations.cells.push(tokens.iter().map(|token| token.location).collect());
+        Ok(())
+    }
+
+    fn parse_property(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let name = tokens[0];
+
+        // Split off the value, if there is one
+        let (coordinates, value) = match tokens.iter().position(|token| token.text == "=") {
+            Some(i) => (&tokens[1..i], tokens.get(i + 1..).filter(|value| !value.is_empty())),
+            None => (&tokens[1..], None)
+        };
+
+        let (from, to) = match coordinates {
+            [x, y] => ((self.number(*x)?, self.number(*y)?), (self.number(*x)?, self.number(*y)?)),
+            [x1, y1, x2, y2] => ((self.number(*x1)?, self.number(*y1)?), (self.number(*x2)?, self.number(*y2)?)),
+            _ => return Err(self.error(name.location, "Expected '<property> <x> <y> [<to x> <to y>] [= <value>]'"))
+        };
+
+        if to.0 < from.0 || to.1 < from.1 {
+            return Err(self.error(coordinates[2].location, "The end of the rectangle must not be before its start"))
+        }
+
+        let takes_value = matches!(name.text, "floor" | "ceiling" | "height");
+
+        let value = match (value, takes_value) {
+            (Some([value]), true) => *value,
+            (Some(value), true) => return Err(self.error(value[1].location, "Expected a single value")),
+            (None, true) => return Err(self.error(name.location, format!("Expected a value for '{}'", name.text))),
+            (Some(value), false) => return Err(self.error(value[0].location, format!("'{}' doesn't take a value", name.text))),
+            (None, false) => name
+        };
+
+        let property = match name.text {
+            "floor" | "ceiling" => {
+                let texture: i32 = self.number(value)?;
+                // The texture section has to come first, so the ids can be checked here
+                if texture < 0 || texture as usize > self.textures.len() {
+                    return Err(self.error(value.location, format!("Texture id {texture} is not in the texture list")))
+                }
+                if name.text == "floor" { Property::Floor(texture) } else { Property::Ceiling(texture) }
+            }
+            "height" => {
+                let height: f32 = self.number(value)?;
+                if !(height > 0.0 && height.is_finite()) {
+                    return Err(self.error(value.location, "Wall height must be above 0"))
+                }
+                Property::Height(height)
+            }
+            "open_sky" => Property::OpenSky,
+            other => return Err(self.error(name.location, format!("Unknown property '{other}'")))
+        };
+
+        // The size of the world isn't known until all the cells have been read
+        self.properties.push(PropertyEntry { location: coordinates[0].location, from, to, property });
+        Ok(())
+    }
+
+    fn parse_cell_type(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let (id, value) = self.key_value(tokens)?;
+        let id_value: usize = self.number(id)?;
+
+        if id_value == 0 {
+            return Err(self.error(id.location, "Cell type 0 is always an empty cell"))
+        }
+
+        // Textures come first, followed by the flags
+        let n_faces = value.iter().take_while(|token| token.text.parse::<i32>().is_ok()).count();
+
+        let faces = match &value[..n_faces] {
+            [texture] => [self.number(*texture)?; 4],
+            [north, south, east, west] => [self.number(*north)?, self.number(*south)?, self.number(*east)?, self.number(*west)?],
+            _ => return Err(self.error(value[0].location, "Expected '<texture>' or '<north> <south> <east> <west>' textures"))
+        };
+
+        let mut cell_type = CellType { faces, solid: false, transparent: false };
+
+        for flag in &value[n_faces..] {
+            match flag.text {
+                "solid" => cell_type.solid = true,
+                "transparent" => cell_type.transparent = true,
+                other => return Err(self.error(flag.location, format!("Unknown cell type flag '{other}', expected 'solid' or 'transparent'")))
+            }
+        }
+
+        self.cell_types.push((id.location, id_value, cell_type));
+        Ok(())
+    }
+
+    fn parse_door(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let [x, y] = tokens else {
+            return Err(self.error(tokens[0].location, "Expected '<x> <y>'"))
+        };
+
+        self.doors.push((self.number(*x)?, self.number(*y)?));
+        self.locations.doors.push(x.location);
+        Ok(())
+    }
+
+    fn parse_sprite(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let [x, y, texture] = tokens else {
+            return Err(self.error(tokens[0].location, "Expected '<x> <y> <texture>'"))
+        };
+
+        self.sprites.push(Sprite::new(self.number(*x)?, self.number(*y)?, self.number(*texture)?));
+        self.locations.sprites.push(x.location);
+        Ok(())
+    }
+
+    fn parse_image(&mut self, tokens: &[Token]) -> Result<(), Error> {
+        let (key, value) = self.key_value(tokens)?;
+
+        if key.text == "file" {
+            if value.len() > 1 {
+                return Err(self.error(value[1].location, "Image file names can't contain spaces"))
+            }
+            self.image = Some((value[0].location, value[0].text.to_string()));
+            return Ok(())
+        }
+
+        let colour = u32::from_str_radix(key.text, 16)
+            .ok()
+            .filter(|_| key.text.len() == 6)
+            .ok_or_else(|| self.error(key.location, format!("Expected 'file' or an RGB hex colour such as 'ff8000', found '{}'", key.text)))?;
+
+        let entry = match value {
+            [spawn] if spawn.text == "spawn" => PaletteEntry::Spawn,
+            [sprite, texture] if sprite.text == "sprite" => PaletteEntry::Sprite(self.number(*texture)?),
+            [cell] => PaletteEntry::Cell(self.number(*cell)?),
+            _ => return Err(self.error(value[0].location, "Expected '<cell id>', 'spawn' or 'sprite <texture>'"))
+        };
+
+        if self.palette.entries.contains_key(&colour) {
+            return Err(self.error(key.location, format!("Colour {} is already in the palette", key.text)))
+        }
+
+        self.palette.insert(colour, entry);
+        Ok(())
+    }
+
+    fn number<T: std::str::FromStr>(&self, token: Token) -> Result<T, Error> {
+        token.text.parse()
+            .map_err(|_| self.error(token.location, format!("Expected a number, found '{}'", token.text)))
+    }
+
+    /// Checks required sections were given and builds the map.
+    fn finish(mut self) -> Result<Map, Error> {
+        let end = Location { line: self.last_line.max(1), column: 1 };
+
+        if let Some((location, file)) = self.image.clone() {
+            if !self.rows.is_empty() {
+                return Err(self.error(location, "Map can't have both [cells] and an [image]"))
+            }
+            self.read_image(location, &file)?;
+        }
+
+        let Some(spawn_position) = self.spawn_position else {
+            return Err(self.error(end, "Map has no spawn position"))
+        };
+
+        let world = Grid::from_rows(std::mem::take(&mut self.rows))
+            .ok_or_else(|| self.error(end, "World rows are not all the same length"))?;
+
+        if world.width() == 0 || world.height() == 0 {
+            return Err(self.error(end, "Map has no cells"))
+        }
+
+        // Every texture can be used as a cell on its own
+        let mut cell_types: Vec<CellType> = (1..=self.textures.len() as i32).map(CellType::uniform).collect();
+        self.locations.cell_types = vec![Location::default(); cell_types.len()];
+
+        for (location, id, cell_type) in &self.cell_types {
+            if *id <= cell_types.len() {
+                cell_types[id - 1] = cell_type.clone();
+                self.locations.cell_types[id - 1] = *location;
+            } else if *id == cell_types.len() + 1 {
+                cell_types.push(cell_type.clone());
+                self.locations.cell_types.push(*location);
+            } else {
+                return Err(self.error(*location, format!("Expected cell type id {} or lower, found {id}", cell_types.len() + 1)))
+            }
+        }
+
+        let mut cell_properties = Grid::new(world.width(), world.height(), CellProperties::default());
+
+        for PropertyEntry { location, from, to, property } in &self.properties {
+            if to.0 >= world.width() || to.1 >= world.height() {
+                return Err(self.error(*location, format!("Rectangle reaches outside of the {}x{} world", world.width(), world.height())))
+            }
+
+            for x in from.0..=to.0 {
+                for properties in &mut cell_properties[x][from.1..=to.1] {
+                    match *property {
+                        Property::Floor(texture) => properties.floor_texture = texture,
+                        Property::Ceiling(texture) => properties.ceiling_texture = texture,
+                        Property::Height(height) => properties.wall_height = height,
+                        Property::OpenSky => properties.open_sky = true
+                    }
+                }
+            }
+        }
+
+        Ok(Map {
+            metadata: self.metadata,
+            textures: self.textures,
+            cell_types,
+            spawn_position,
+            spawn_direction: self.spawn_direction,
+            exit: self.exit,
+            world,
+            cell_properties,
+            doors: self.doors,
+            sprites: self.sprites,
+            layout_image: self.layout_image,
+            locations: self.locations
+        })
+    }
+
+    /// Fills in the cells, spawn and sprites from the layout image `file`,
+    /// which is relative to the map file.
+    fn read_image(&mut self, location: Location, file: &str) -> Result<(), Error> {
+        let path = Path::new(self.file).parent().unwrap_or(Path::new("")).join(file);
+        let palette = if self.palette.entries.is_empty() { Palette::default() } else { self.palette.clone() };
+
+        let layout = image_map::load_layout(&path, &palette)
+            .map_err(|err| self.error(location, err))?;
+
+        match (layout.spawn, self.spawn_position) {
+            (Some(_), Some(_)) => return Err(self.error(self.locations.spawn, "Spawn is set in both [spawn] and the image")),
+            (Some(spawn), None) => {
+                self.spawn_position = Some(spawn);
+                self.locations.spawn = location;
+            }
+            _ => {}
+        }
+
+        for sprite in layout.sprites {
+            self.sprites.push(sprite);
+            self.locations.sprites.push(location);
+        }
+
+        // Every problem with a cell is pointed at the image
+        self.rows = layout.world.rows().map(|row| row.to_vec()).collect();
+        self.locations.cells = vec![vec![location; layout.world.height()]; layout.world.width()];
+        self.layout_image = Some((path, palette));
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,218 @@
+//! Contains the procedural maze generator.
+//!
+//! Mazes are carved out of a grid of passages, where passage (i, j) is the world cell
+//! (2i + 1, 2j + 1) and the cells between passages are either walls or openings.
+//! The same options always give the same maze.
+
+use crate::{
+    game::CellProperties,
+    grid::Grid,
+    map::Map,
+    util::{Rng, Vector2}
+};
+
+/// Texture ids of the walls, most walls use the first one
+const WALL_TEXTURES: [i32; 4] = [1, 2, 3, 4];
+/// Texture id of the floor of the exit cell
+const EXIT_TEXTURE: i32 = 5;
+
+/// How a maze should be generated.
+#[derive(Clone, Copy)]
+pub struct MazeOptions {
+    /// Number of passages along x
+    pub width: usize,
+    /// Number of passages along y
+    pub height: usize,
+    /// Chance of each dead end being opened up into a loop.
+    /// 0.0 gives a perfect maze, with exactly one route between any two places,
+    /// and 1.0 gives a maze with no dead ends.
+    pub braid: f32,
+    pub seed: u64
+}
+
+impl Default for MazeOptions {
+    fn default() -> Self {
+        Self {
+            width: 12,
+            height: 12,
+            braid: 0.0,
+            seed: 0
+        }
+    }
+}
+
+/// File names of the textures used by generated mazes, texture id `n` is at index `n-1`.
+pub fn textures() -> Vec<String> {
+    ["DUNGEONBRICKS.png", "ROUNDBRICKS.png", "GRAYWALL.png", "PIPES.png", "CROSSCUBE.png"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// Generates a maze, with the spawn in a random passage and the exit
+/// in the passage furthest away from it.
+pub fn generate(options: &MazeOptions) -> Map {
+    let width = options.width.max(1);
+    let height = options.height.max(1);
+    let mut rng = Rng::new(options.seed);
+
+    // Every cell starts as a wall, textures are chosen once the layout is done
+    let mut world = Grid::new(width * 2 + 1, height * 2 + 1, 1);
+
+    carve_passages(&mut world, width, height, &mut rng);
+    braid(&mut world, width, height, options.braid, &mut rng);
+
+    // Long runs of the same texture with the odd different wall, so places can be told apart
+    for x in 0..world.width() {
+        for y in 0..world.height() {
+            if world[x][y] != 0 && rng.chance(0.2) {
+                world[x][y] = WALL_TEXTURES[1 + rng.range(WALL_TEXTURES.len() - 1)];
+            }
+        }
+    }
+
+    let spawn = (rng.range(width) * 2 + 1, rng.range(height) * 2 + 1);
+    // The flood fill reaches the furthest cell last
+    let exit = *world.flood_fill(spawn, |_, _, cell| *cell == 0).last().unwrap_or(&spawn);
+
+    let mut map = Map::new(textures(), world, Vector2::new(spawn.0 as f32 + 0.5, spawn.1 as f32 + 0.5));
+
+    map.metadata.name = format!("Maze {}x{} (seed {})", width, height, options.seed);
+    map.exit = Some(exit);
+    map.cell_properties[exit.0][exit.1] = CellProperties {
+        floor_texture: EXIT_TEXTURE,
+        ..CellProperties::default()
+    };
+
+    // Face down the passage leading out of the spawn
+    let (spawn_x, spawn_y) = spawn;
+    if let Some((dx, dy)) = DIRECTIONS.into_iter()
+        .find(|(dx, dy)| map.world[spawn_x.wrapping_add_signed(*dx)][spawn_y.wrapping_add_signed(*dy)] == 0)
+    {
+        map.spawn_direction = Vector2::new(dx as f32, dy as f32);
+    }
+
+    map
+}
+
+/// Steps to the neighbouring cells, in the order north, south, west, east.
+const DIRECTIONS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+/// Carves a perfect maze with a randomised depth first search.
+fn carve_passages(world: &mut Grid<i32>, width: usize, height: usize, rng: &mut Rng) {
+    let mut visited = Grid::new(width, height, false);
+    let start = (rng.range(width), rng.range(height));
+    let mut stack = vec![start];
+
+    visited[start.0][start.1] = true;
+    world[start.0 * 2 + 1][start.1 * 2 + 1] = 0;
+
+    while let Some(&(i, j)) = stack.last() {
+        let mut directions = DIRECTIONS;
+        rng.shuffle(&mut directions);
+
+        let next = directions.into_iter()
+            .map(|(di, dj)| (i.wrapping_add_signed(di), j.wrapping_add_signed(dj)))
+            .find(|&(ni, nj)| visited.get(ni, nj) == Some(&false));
+
+        let Some((ni, nj)) = next else {
+            stack.pop();
+            continue
+        };
+
+        visited[ni][nj] = true;
+        // Opens the wall between the passages as well as the new passage
+        world[i + ni + 1][j + nj + 1] = 0;
+        world[ni * 2 + 1][nj * 2 + 1] = 0;
+        stack.push((ni, nj));
+    }
+}
+
+/// Opens up dead ends with a chance of `amount`, preferring to join two dead ends together.
+fn braid(world: &mut Grid<i32>, width: usize, height: usize, amount: f32, rng: &mut Rng) {
+    if amount <= 0.0 { return }
+
+    let mut passages: Vec<(usize, usize)> = (0..width)
+        .flat_map(|i| (0..height).map(move |j| (i, j)))
+        .collect();
+    rng.shuffle(&mut passages);
+
+    for (i, j) in passages {
+        let (x, y) = (i * 2 + 1, j * 2 + 1);
+        let open_sides = DIRECTIONS.iter()
+            .filter(|(dx, dy)| world[x.wrapping_add_signed(*dx)][y.wrapping_add_signed(*dy)] == 0)
+            .count();
+
+        // Opening one dead end can also fix a neighbouring one, so each passage is checked when it is reached
+        if open_sides != 1 || !rng.chance(amount) { continue }
+
+        let mut walls: Vec<(isize, isize)> = DIRECTIONS.into_iter()
+            .filter(|(dx, dy)| {
+                let (wall_x, wall_y) = (x.wrapping_add_signed(*dx), y.wrapping_add_signed(*dy));
+                world[wall_x][wall_y] != 0 && !world.on_boundary(wall_x, wall_y)
+            })
+            .collect();
+        rng.shuffle(&mut walls);
+
+        let is_dead_end = |world: &Grid<i32>, (dx, dy): (isize, isize)| {
+            let (next_x, next_y) = (x.wrapping_add_signed(dx * 2), y.wrapping_add_signed(dy * 2));
+            DIRECTIONS.iter()
+                .filter(|(ex, ey)| world[next_x.wrapping_add_signed(*ex)][next_y.wrapping_add_signed(*ey)] == 0)
+                .count() == 1
+        };
+
+        let chosen = walls.iter().copied()
+            .find(|&direction| is_dead_end(world, direction))
+            .or(walls.first().copied());
+
+        if let Some((dx, dy)) = chosen {
+            world[x.wrapping_add_signed(dx)][y.wrapping_add_signed(dy)] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::validate;
+
+    #[test]
+    fn same_seed_gives_the_same_maze() {
+        for braid in [0.0, 0.5, 1.0] {
+            for seed in [0, 1, 42, u64::MAX] {
+                let options = MazeOptions { width: 15, height: 9, braid, seed };
+                let (first, second) = (generate(&options), generate(&options));
+                let name = &first.metadata.name;
+
+                assert!(first.world.rows().eq(second.world.rows()), "{name} braid {braid}: cells differ");
+                assert_eq!(
+                    (first.spawn_position.x, first.spawn_position.y),
+                    (second.spawn_position.x, second.spawn_position.y),
+                    "{name} braid {braid}: spawn differs"
+                );
+                assert_eq!(first.exit, second.exit, "{name} braid {braid}: exit differs");
+            }
+        }
+    }
+
+    #[test]
+    fn exit_can_be_reached_from_the_spawn() {
+        for (width, height) in [(1, 1), (2, 7), (12, 12), (30, 20)] {
+            for braid in [0.0, 0.5, 1.0] {
+                for seed in 0..20 {
+                    let map = generate(&MazeOptions { width, height, braid, seed });
+                    let name = &map.metadata.name;
+
+                    for diagnostic in validate(&map) {
+                        assert!(!diagnostic.is_error(), "{name} braid {braid}: {}", diagnostic.problem);
+                    }
+
+                    let spawn = (map.spawn_position.x as usize, map.spawn_position.y as usize);
+                    let exit = map.exit.expect("Mazes always have an exit");
+                    let reached = map.world.flood_fill(spawn, |_, _, cell| *cell == 0);
+                    assert!(reached.contains(&exit), "{name} braid {braid}: exit can't be reached");
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -1,22 +1,137 @@
 use crate::util::Vector2;
 
+/// Height of the camera above the floor while standing, in cells
+const STANDING_EYE_HEIGHT: f32 = 0.5;
+/// Height of the camera above the floor while crouching, in cells
+const CROUCHING_EYE_HEIGHT: f32 = 0.3;
+/// Furthest the camera can look up or down, as a fraction of the screen height
+const MAX_PITCH: f32 = 0.5;
+
+#[derive(Clone)]
 pub struct Player {
+    /// Top walking speed, in cells per second
     pub move_speed: f32,
+    /// How quickly the player speeds up to the top speed, in cells per second per second
+    pub acceleration: f32,
+    /// How quickly the player slows down on the ground when not moving, in cells per second per second
+    pub friction: f32,
+    /// The top speed is multiplied by this while sprinting
+    pub sprint_multiplier: f32,
     pub rot_speed: f32,
+    pub pitch_speed: f32,
+    /// The player collides with walls as a circle this wide, in cells
+    pub radius: f32,
     pub position: Vector2<f32>,
+    /// Horizontal movement, in cells per second
+    pub velocity: Vector2<f32>,
     pub camera_direction: Vector2<f32>,
-    pub camera_plane: Vector2<f32>
+    pub camera_plane: Vector2<f32>,
+    /// Vertical look, moves the horizon by this fraction of the screen height.
+    /// Positive values look up.
+    pub pitch: f32,
+    /// Height of the player's feet above the floor, only above 0 while jumping
+    pub vertical_position: f32,
+    pub vertical_velocity: f32,
+    pub jump_speed: f32,
+    pub gravity: f32,
+    pub crouching: bool
 }
 
 impl Player {
     pub fn new() -> Player {
         Player {
             move_speed: 10.0,
+            acceleration: 80.0,
+            friction: 60.0,
+            sprint_multiplier: 1.5,
             rot_speed: 5.0,
+            pitch_speed: 1.5,
+            radius: 0.2,
             position: Vector2::new(22.0, 12.0),
+            velocity: Vector2::new(0.0, 0.0),
             camera_direction: Vector2::new(-1.0, 0.0),
-            camera_plane: Vector2::new(0.0, 0.66)
+            camera_plane: Vector2::new(0.0, 0.66),
+            pitch: 0.0,
+            vertical_position: 0.0,
+            vertical_velocity: 0.0,
+            jump_speed: 3.0,
+            gravity: 12.0,
+            crouching: false
         }
     }
+
+    /// Points the camera along `direction`, which must be normalised.
+    /// The field of view is kept the same.
+    pub fn face(&mut self, direction: Vector2<f32>) {
+        let plane_length = (self.camera_plane.x * self.camera_plane.x
+            + self.camera_plane.y * self.camera_plane.y).sqrt();
+
+        self.camera_direction = direction;
+        // The camera plane is perpendicular to the direction, to its right
+        self.camera_plane = Vector2::new(direction.y * plane_length, -direction.x * plane_length);
+    }
+
+    /// Turns the camera by `angle` radians, positive angles turn left.
+    pub fn rotate(&mut self, angle: f32) {
+        // Credit to [Lode's Computer Graphics Tutorial](https://lodev.org/cgtutor/raycasting.html)
+        // for rotation code
+        let (sin, cos) = angle.sin_cos();
+        let rotate = |v: Vector2<f32>| Vector2::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos);
+
+        self.camera_direction = rotate(self.camera_direction);
+        self.camera_plane = rotate(self.camera_plane);
+    }
+
+    /// Moves the vertical look by `amount`, positive amounts look up.
+    /// The pitch is kept within `MAX_PITCH`.
+    pub fn tilt(&mut self, amount: f32) {
+        self.pitch = (self.pitch + amount).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Returns the normalised direction to the right of the camera.
+    pub fn right(&self) -> Vector2<f32> {
+        let length = (self.camera_plane.x * self.camera_plane.x
+            + self.camera_plane.y * self.camera_plane.y).sqrt();
+        Vector2::new(self.camera_plane.x / length, self.camera_plane.y / length)
+    }
+
+    /// Height of the camera above the floor, in cells.
+    pub fn eye_height(&self) -> f32 {
+        let base = if self.crouching { CROUCHING_EYE_HEIGHT } else { STANDING_EYE_HEIGHT };
+        base + self.vertical_position
+    }
+
+    /// Returns true if the player is standing on the floor.
+    pub fn on_ground(&self) -> bool {
+        self.vertical_position <= 0.0
+    }
+
+    /// Returns the screen row of the horizon for a screen `screen_height` pixels tall.
+    pub fn horizon(&self, screen_height: u32) -> i32 {
+        (screen_height / 2) as i32 + (self.pitch * screen_height as f32) as i32
+    }
+
+    /// Returns the player part way between `previous` and `self`, where `alpha` 0 is `previous` and 1 is `self`.
+    /// Used to draw the player smoothly between two simulation steps.
+    pub fn interpolate(&self, previous: &Player, alpha: f32) -> Player {
+        let lerp = |a: f32, b: f32| a + (b - a) * alpha;
+
+        let mut player = self.clone();
+        player.position = Vector2::new(
+            lerp(previous.position.x, self.position.x),
+            lerp(previous.position.y, self.position.y)
+        );
+        player.pitch = lerp(previous.pitch, self.pitch);
+        player.vertical_position = lerp(previous.vertical_position, self.vertical_position);
+
+        // Turn through the smaller angle between the two directions
+        let previous_angle = previous.camera_direction.y.atan2(previous.camera_direction.x);
+        let angle = self.camera_direction.y.atan2(self.camera_direction.x);
+        let turn = (angle - previous_angle + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI;
+        let angle = previous_angle + turn * alpha;
+        player.face(Vector2::new(angle.cos(), angle.sin()));
+
+        player
+    }
     
 }
\ No newline at end of file
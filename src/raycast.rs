@@ -0,0 +1,500 @@
+//! Contains the per-column raycasting used by the renderer.
+//!
+//! Every screen column is cast and textured independently of the others,
+//! which allows the frame to be split into bands and drawn in parallel.
+
+use crate::{
+    cell::Face,
+    door::DoorAxis,
+    game::GameContext,
+    render::{RenderContext, SkyMode},
+    texture::{SKY_HEIGHT, SKY_WIDTH, TEXTURE_WIDTH},
+    util::Vector2
+};
+
+/// The result of casting the ray for a single screen column.
+#[derive(Clone, Copy)]
+pub struct ColumnHit {
+    /// Exact world coordinates where the ray hit the first solid wall
+    pub ray_intersection: Vector2<f32>
+}
+
+impl Default for ColumnHit {
+    fn default() -> Self {
+        Self {
+            ray_intersection: Vector2::new(0.0, 0.0)
+        }
+    }
+}
+
+/// A wall hit by the ray of a column.
+struct WallLayer {
+    perpendicular_wall_dist: f32,
+    /// Distance at which the ray leaves the cell on the far side,
+    /// `None` if the top of the wall can't be seen
+    exit_dist: Option<f32>,
+    /// Height of the wall in world units
+    height: f32,
+    /// Number of times the texture is repeated up the wall
+    texture_repeats: f32,
+    texture_index: usize,
+    /// Where along the wall the ray hit, from 0.0 to 1.0
+    wall_x: f32,
+    side_facing_x_axis: bool,
+    transparent: bool
+}
+
+/// Everything needed to draw a column of the frame.
+///
+/// Only holds shared references, so a single view can be used by several threads at once.
+pub struct FrameView<'a> {
+    context: &'a GameContext,
+    render_context: &'a RenderContext,
+    image_textures: &'a [Box<[u32]>],
+    sky_texture: Option<&'a [u32]>,
+    width: u32,
+    height: u32,
+    // No wall is taller than this, which limits how far past a wall something can still be seen
+    tallest_wall: f32
+}
+
+impl<'a> FrameView<'a> {
+
+    pub fn new(context: &'a GameContext, render_context: &'a RenderContext,
+        image_textures: &'a [Box<[u32]>], sky_texture: Option<&'a [u32]>, width: u32, height: u32) -> Self {
+        Self {
+            context,
+            render_context,
+            image_textures,
+            sky_texture,
+            width, height,
+            tallest_wall: context.tallest_wall() * render_context.wall_height as f32
+        }
+    }
+
+    /// Casts the ray for `column_index` and draws the floor, ceiling and walls
+    /// of that column into `column`, which holds `height` pixels from top to bottom.
+    /// 
+    /// The distance to whatever was drawn in each pixel is written to `depth`,
+    /// floor and ceiling pixels are treated as infinitely far away.
+    /// 
+    /// The ray continues past walls which are see-through or short enough to see over,
+    /// keeping track of the highest row covered by a solid wall so far (the clip).
+    /// Walls which are completely below the clip are hidden and skipped, and the ray stops
+    /// once the clip reaches the top of the screen or nothing further away could rise above it.
+    pub fn draw_column(&self, column_index: u32, column: &mut [u32], depth: &mut [f32]) -> ColumnHit {
+
+        let context = self.context;
+
+        let pos_x = context.player.position.x;
+        let pos_y = context.player.position.y;
+
+        let camera_x: f32 = ((2 * column_index) as f32 / self.width as f32) - 1.0;
+
+        let ray_dir_x = context.player.camera_direction.x + context.player.camera_plane.x * camera_x;
+        let ray_dir_y = context.player.camera_direction.y + context.player.camera_plane.y * camera_x;
+
+        self.draw_background(ray_dir_x, ray_dir_y, column);
+        depth.fill(f32::INFINITY);
+
+        let mut map_x = pos_x as i32;
+        let mut map_y = pos_y as i32;
+
+        let delta_dist_x = if ray_dir_x == 0.0 {f32::MAX} else {(1.0/ray_dir_x).abs()};
+        let delta_dist_y = if ray_dir_y == 0.0 {f32::MAX} else {(1.0/ray_dir_y).abs()};
+
+        let step_x: i32;
+        let step_y: i32;
+
+        let mut side_dist_x: f32;
+        let mut side_dist_y: f32;
+
+        if ray_dir_x < 0.0 {
+            step_x = -1;
+            side_dist_x = (pos_x - map_x as f32) * delta_dist_x
+        } else {
+            step_x = 1;
+            side_dist_x = ((map_x + 1) as f32 - pos_x) * delta_dist_x;
+        }
+
+        if ray_dir_y < 0.0 {
+            step_y = -1;
+            side_dist_y = (pos_y - map_y as f32) * delta_dist_y;
+        } else {
+            step_y = 1;
+            side_dist_y = ((map_y + 1) as f32 - pos_y) * delta_dist_y;
+        }
+
+        let mut side_facing_x_axis;
+
+        // Every wall the ray hits, from nearest to furthest.
+        // The ray keeps going past see-through walls, so it can hit several.
+        let mut layers: Vec<WallLayer> = Vec::new();
+
+        // Rows from the clip down are covered by a solid wall nearer than the ray has reached.
+        // Walls all stand on the floor, so a further wall can only be seen above a nearer one.
+        let mut clip = self.height as f32;
+        let horizon = context.player.horizon(self.height) as f32;
+
+        // The minimap shows the ray reaching the first solid wall, whether or not it is on screen
+        let mut first_solid_dist = None;
+        let mut last_hit_dist = 0.0;
+
+        loop {
+            if side_dist_x < side_dist_y {
+                side_dist_x += delta_dist_x;
+                map_x += step_x;
+                side_facing_x_axis = true;
+            } else {
+                side_dist_y += delta_dist_y;
+                map_y += step_y;
+                side_facing_x_axis = false;
+            }
+
+            // The ray can only leave the world if it has passed over the boundrary walls
+            let Some(&cell) = context.world.get(map_x as usize, map_y as usize)
+                else { break };
+
+            let Some(cell_type) = context.cell_type(cell) else { continue };
+
+            let properties = &context.cell_properties[map_x as usize][map_y as usize];
+            let wall_height = properties.wall_height * self.render_context.wall_height as f32;
+
+            // Distance to the wall and how far along the wall it was hit
+            let hit = match context.doors.get(&(map_x as usize, map_y as usize)) {
+                // Doors are recessed to the middle of their cell, so the ray can
+                // pass through the cell without hitting them
+                Some(door) => if side_facing_x_axis == (door.axis == DoorAxis::X) {
+                    // The ray reaches the middle of the cell half a step after entering it
+                    let (door_dist, other_side_dist) = if side_facing_x_axis {
+                        (side_dist_x - delta_dist_x / 2.0, side_dist_y)
+                    } else {
+                        (side_dist_y - delta_dist_y / 2.0, side_dist_x)
+                    };
+
+                    let along_door = if side_facing_x_axis {
+                        pos_y + door_dist * ray_dir_y - map_y as f32
+                    } else {
+                        pos_x + door_dist * ray_dir_x - map_x as f32
+                    };
+
+                    // If the ray leaves through the side of the cell first it hits the door frame instead,
+                    // and the door has slid out of the first `open_amount` of the cell
+                    if door_dist < other_side_dist && along_door >= door.open_amount {
+                        Some((door_dist, along_door - door.open_amount))
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+                None => {
+                    let perpendicular_wall_dist = if side_facing_x_axis {
+                        side_dist_x - delta_dist_x
+                    } else {
+                        side_dist_y - delta_dist_y
+                    };
+
+                    // Exact coordinate along the wall the ray intersects with
+                    let wall_x = if side_facing_x_axis {
+                        pos_y + perpendicular_wall_dist * ray_dir_y
+                    } else {
+                        pos_x + perpendicular_wall_dist * ray_dir_x
+                    };
+
+                    Some((perpendicular_wall_dist, wall_x - wall_x.floor()))
+                }
+            };
+
+            if let Some((perpendicular_wall_dist, wall_x)) = hit {
+                // Doors are too thin to have a visible top
+                let exit_dist = if context.doors.contains_key(&(map_x as usize, map_y as usize)) {
+                    None
+                } else {
+                    Some(side_dist_x.min(side_dist_y))
+                };
+
+                let layer = WallLayer {
+                    perpendicular_wall_dist,
+                    exit_dist,
+                    height: wall_height,
+                    texture_repeats: properties.wall_height,
+                    texture_index: cell_type.texture(Face::hit_by(side_facing_x_axis, step_x, step_y)) as usize - 1,
+                    wall_x,
+                    side_facing_x_axis,
+                    transparent: cell_type.transparent
+                };
+
+                last_hit_dist = perpendicular_wall_dist;
+                if !layer.transparent && first_solid_dist.is_none() {
+                    first_solid_dist = Some(perpendicular_wall_dist);
+                }
+
+                // Walls completely below the clip are hidden behind nearer walls
+                let layer_top = self.covered_top(&layer);
+                if layer_top < clip {
+                    if !layer.transparent {
+                        clip = layer_top;
+                    }
+                    layers.push(layer);
+                }
+
+                // Walls drawn at a fixed height all cover the same rows
+                let fixed_height_covered = self.render_context.dev_fixed_wall_height && clip < self.height as f32;
+                if clip <= 0.0 || fixed_height_covered { break }
+
+                // The highest any further wall could reach on screen. Walls taller than the camera
+                // rise higher the nearer they are, and walls lower than the camera stay below the horizon.
+                let highest_further = horizon + ((self.eye_height() - self.tallest_wall) * self.height as f32 / perpendicular_wall_dist).min(0.0);
+                if highest_further >= clip { break }
+            }
+        }
+
+        // Draw from back to front, so see-through and shorter walls are drawn over whatever is behind them
+        for layer in layers.iter().rev() {
+            self.draw_wall(layer, ray_dir_x, ray_dir_y, column, depth);
+        }
+
+        // Rays which leave the world have nothing to show on the minimap beyond the last wall
+        let perpendicular_wall_dist = first_solid_dist.unwrap_or(last_hit_dist);
+
+        ColumnHit {
+            // Calculate the exact x, y coordinates the ray intersects with
+            ray_intersection: Vector2::new(
+                pos_x + perpendicular_wall_dist * ray_dir_x,
+                pos_y + perpendicular_wall_dist * ray_dir_y
+            )
+        }
+    }
+
+    /// Draws a single wall into a column.
+    /// 
+    /// If the wall is see-through, transparent texels are skipped
+    /// and whatever is behind them is left in place.
+    fn draw_wall(&self, layer: &WallLayer, ray_dir_x: f32, ray_dir_y: f32, column: &mut [u32], depth: &mut [f32]) {
+
+        if let Some(exit_dist) = layer.exit_dist {
+            self.draw_wall_top(layer, exit_dist, ray_dir_x, ray_dir_y, column, depth);
+        }
+
+        let (line_height, wall_top) = self.project(layer);
+
+        let draw_start = wall_top.clamp(0.0, self.height as f32) as u32;
+        let draw_end = (wall_top + line_height).clamp(0.0, (self.height - 1) as f32) as u32;
+
+        // The texture repeats once per standard wall height, lined up with the floor,
+        // so shorter walls show the bottom of the texture and taller walls repeat it
+        let step = TEXTURE_WIDTH as f32 * layer.texture_repeats / line_height;
+        let texture_offset = TEXTURE_WIDTH as f32 * (layer.texture_repeats.ceil() - layer.texture_repeats);
+
+        let texture_x = TEXTURE_WIDTH as i32 - (layer.wall_x * TEXTURE_WIDTH as f32) as i32 - 1;
+
+        let mut texture_y = ((draw_start as f32 - wall_top) * step).max(0.0) + texture_offset;
+
+        for y in draw_start..draw_end {
+
+            // Hmm, i don't like this, not sure if it's possible for texture_x to go below 0
+            let mut colour = self.image_textures[layer.texture_index][TEXTURE_WIDTH * (texture_y as usize % TEXTURE_WIDTH) + texture_x as usize];
+
+            texture_y += step;
+
+            // Let whatever is behind show through transparent texels
+            if layer.transparent && colour & 0xFF == 0 { continue }
+
+            if !layer.side_facing_x_axis {
+                // Extract the r,g,b,a components and
+                // right shift the r,g,b values to half them
+                let (r, g, b, a) = (
+                    colour >> 24 + 1,
+                    (colour >> 16 & 0xFF) >> 1,
+                    (colour >> 8 & 0xFF) >> 1,
+                    colour & 0xFF
+                );
+
+                // Shift the values into their correct place
+                // And combine them
+                colour = r << 24 | g << 16 | b << 8 | a;
+            }
+
+            if let Some(fog) = &self.render_context.fog {
+                colour = fog.apply(colour, layer.perpendicular_wall_dist);
+            }
+
+            column[y as usize] = colour;
+            depth[y as usize] = layer.perpendicular_wall_dist;
+        }
+    }
+
+    /// Returns the height on screen of the face of a wall and the screen row of its top edge.
+    /// The bottom edge sits on the floor, so the wall moves up and down with the height of the camera.
+    fn project(&self, layer: &WallLayer) -> (f32, f32) {
+        let horizon = self.context.player.horizon(self.height) as f32;
+
+        if self.render_context.dev_fixed_wall_height {
+            let line_height = self.render_context.dev_fixed_wall_height_value as f32;
+            (line_height, horizon - line_height / 2.0)
+        } else {
+            let scale = self.height as f32 / layer.perpendicular_wall_dist;
+            (layer.height * scale, horizon + (self.eye_height() - layer.height) * scale)
+        }
+    }
+
+    /// Returns the highest screen row a wall covers, including its top if that can be seen.
+    fn covered_top(&self, layer: &WallLayer) -> f32 {
+        let (_, wall_top) = self.project(layer);
+        let camera_height = (self.eye_height() - layer.height) * self.height as f32;
+
+        match layer.exit_dist {
+            // The far edge of the top is higher up the screen than the near edge
+            Some(exit_dist) if camera_height > 0.0 => {
+                let horizon = self.context.player.horizon(self.height) as f32;
+                wall_top.min(horizon + camera_height / exit_dist)
+            }
+            _ => wall_top
+        }
+    }
+
+    /// Draws the top of a wall which is lower than the camera,
+    /// by projecting each pixel onto the plane at the top of the wall.
+    fn draw_wall_top(&self, layer: &WallLayer, exit_dist: f32, ray_dir_x: f32, ray_dir_y: f32, column: &mut [u32], depth: &mut [f32]) {
+
+        // Height of the camera above the top of the wall, in screen space
+        let camera_height = (self.eye_height() - layer.height) * self.height as f32;
+
+        if camera_height <= 0.0 { return }
+
+        let horizon = self.context.player.horizon(self.height) as f32;
+
+        // The top is seen between where the ray enters and leaves the cell
+        let draw_start = (horizon + camera_height / exit_dist).clamp(0.0, self.height as f32) as u32;
+        let draw_end = (horizon + camera_height / layer.perpendicular_wall_dist).clamp(0.0, self.height as f32) as u32;
+
+        let pos_x = self.context.player.position.x;
+        let pos_y = self.context.player.position.y;
+
+        for y in draw_start..draw_end {
+            let row_distance = camera_height / (y as f32 - horizon).max(1.0);
+
+            let point_x = pos_x + row_distance * ray_dir_x;
+            let point_y = pos_y + row_distance * ray_dir_y;
+
+            let texture_x = ((point_x - point_x.floor()) * TEXTURE_WIDTH as f32) as usize;
+            let texture_y = ((point_y - point_y.floor()) * TEXTURE_WIDTH as f32) as usize;
+
+            let mut colour = self.image_textures[layer.texture_index]
+                [TEXTURE_WIDTH * texture_y.min(TEXTURE_WIDTH - 1) + texture_x.min(TEXTURE_WIDTH - 1)];
+
+            if layer.transparent && colour & 0xFF == 0 { continue }
+
+            if let Some(fog) = &self.render_context.fog {
+                colour = fog.apply(colour, row_distance);
+            }
+
+            column[y as usize] = colour;
+            depth[y as usize] = row_distance;
+        }
+    }
+
+    /// Draws the floor and ceiling of a column.
+    ///
+    /// Each pixel below (or above) the horizon is projected back onto the floor
+    /// (or ceiling) plane, and the texture of the cell it lands in is sampled.
+    /// Cells with no floor or ceiling texture use the flat colours from `RenderContext`.
+    /// The sky is drawn instead of the ceiling where the `SkyMode` allows it.
+    fn draw_background(&self, ray_dir_x: f32, ray_dir_y: f32, column: &mut [u32]) {
+
+        let pos_x = self.context.player.position.x;
+        let pos_y = self.context.player.position.y;
+
+        let horizon = self.context.player.horizon(self.height);
+
+        // Heights of the camera above the floor and below the ceiling, in screen space.
+        // Matches the wall projection in `draw_column`, so the floor meets the bottom of the walls.
+        let camera_height = self.eye_height() * self.height as f32;
+        let ceiling_gap = (self.render_context.wall_height as f32 - self.eye_height()) * self.height as f32;
+
+        // Column of the sky texture this ray points towards,
+        // so the sky turns with the camera
+        let sky_x = (-ray_dir_y.atan2(ray_dir_x) / std::f32::consts::TAU).rem_euclid(1.0) * SKY_WIDTH as f32;
+        let sky_x = (sky_x as usize).min(SKY_WIDTH - 1);
+
+        for (y, pixel) in column.iter_mut().enumerate() {
+
+            let rows_from_horizon = y as i32 - horizon;
+            let is_floor = rows_from_horizon >= 0;
+
+            let flat_colour = if is_floor {
+                self.render_context.floor_colour
+            } else {
+                self.render_context.ceil_colour
+            };
+
+            // The row on the horizon is infinitely far away
+            if rows_from_horizon == 0 {
+                *pixel = match &self.render_context.fog {
+                    Some(fog) => fog.apply(flat_colour, f32::INFINITY),
+                    None => flat_colour
+                };
+                continue
+            }
+
+            // Distance to the point on the floor or ceiling this pixel can see
+            let row_distance = if is_floor { camera_height } else { ceiling_gap }
+                / rows_from_horizon.abs() as f32;
+
+            let point_x = pos_x + row_distance * ray_dir_x;
+            let point_y = pos_y + row_distance * ray_dir_y;
+
+            let properties = self.context.cell_properties_at(point_x.floor() as i32, point_y.floor() as i32);
+
+            if !is_floor {
+                if let Some(sky_texture) = self.sky_texture {
+                    let show_sky = match self.render_context.sky_mode {
+                        SkyMode::Off => false,
+                        SkyMode::Everywhere => true,
+                        // Anything outside of the world is open to the sky
+                        SkyMode::OpenCells => properties.is_none_or(|properties| properties.open_sky)
+                    };
+
+                    if show_sky {
+                        // The bottom of the sky texture sits on the horizon, and the
+                        // texture reaches the top of the screen when looking straight ahead
+                        let sky_y = (y as i32 - horizon + (self.height / 2) as i32) as f32
+                            / (self.height / 2) as f32 * SKY_HEIGHT as f32;
+                        let sky_y = (sky_y.max(0.0) as usize).min(SKY_HEIGHT - 1);
+
+                        // The sky is infinitely far away, so no fog is applied
+                        *pixel = sky_texture[SKY_WIDTH * sky_y + sky_x];
+                        continue
+                    }
+                }
+            }
+
+            let texture_id = match properties {
+                Some(properties) if is_floor => properties.floor_texture,
+                Some(properties) => properties.ceiling_texture,
+                None => 0
+            };
+
+            let colour = if texture_id > 0 {
+                let texture_x = ((point_x - point_x.floor()) * TEXTURE_WIDTH as f32) as usize;
+                let texture_y = ((point_y - point_y.floor()) * TEXTURE_WIDTH as f32) as usize;
+                self.image_textures[texture_id as usize - 1]
+                    [TEXTURE_WIDTH * texture_y.min(TEXTURE_WIDTH - 1) + texture_x.min(TEXTURE_WIDTH - 1)]
+            } else {
+                flat_colour
+            };
+
+            *pixel = match &self.render_context.fog {
+                Some(fog) => fog.apply(colour, row_distance),
+                None => colour
+            };
+        }
+    }
+
+    /// Height of the camera above the floor in world units,
+    /// scaled by the wall height like the rest of the world.
+    fn eye_height(&self) -> f32 {
+        self.context.player.eye_height() * self.render_context.wall_height as f32
+    }
+}
\ No newline at end of file
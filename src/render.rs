@@ -5,13 +5,33 @@ use sdl2::{
     pixels::PixelFormatEnum, rect::Rect
 };
 
-use crate::{Error, game::{WORLD_SIZE, GameContext}, texture::TEXTURE_WIDTH};
+use crate::{
+    Error,
+    fog::Fog,
+    game::GameContext,
+    raycast::{ColumnHit, FrameView},
+    cell::Face,
+    texture::TEXTURE_WIDTH
+};
+
+/// Size of each slot in the map editor's texture picker, in pixels
+const PICKER_SLOT_SIZE: u32 = 40;
+const PICKER_SLOT_GAP: u32 = 6;
 
 enum TextureTarget {
     Render,
     Minimap
 }
 
+/// Where the sky texture is drawn instead of the ceiling.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SkyMode {
+    Off,
+    Everywhere,
+    /// Only above cells which are marked as open to the sky
+    OpenCells
+}
+
 pub struct RenderContext {
     pub desired_frame_time: std::time::Duration,
     pub dev_fixed_wall_height: bool,
@@ -20,10 +40,24 @@ pub struct RenderContext {
     pub floor_colour: u32,
     pub ceil_colour: u32,
     pub show_minimap: bool,
+    /// Size of each world cell on the minimap, in pixels
     pub minimap_scale: u32,
-    minimap_scale_px: u32,
+    // Size of the minimap in pixels, which depends on the size of the world
+    minimap_width_px: u32,
+    minimap_height_px: u32,
     pub colour_mapping: fn(i32) -> u32,
-    pub resolution_scaling: bool
+    pub resolution_scaling: bool,
+    /// Number of threads used to raycast the frame, 1 draws everything on the calling thread
+    pub render_threads: usize,
+    /// Distance fog, `None` disables it
+    pub fog: Option<Fog>,
+    /// Has no effect until a sky texture is set with `Renderer::set_sky_texture`
+    pub sky_mode: SkyMode,
+    /// Cell value selected in the map editor.
+    /// While this is set the texture picker is drawn and the minimap shows its grid.
+    pub editor_brush: Option<i32>,
+    /// Cell outlined on the minimap, such as the one under the mouse
+    pub minimap_cursor: Option<(usize, usize)>
 }
 
 impl RenderContext {
@@ -48,27 +82,64 @@ impl RenderContext {
             ceil_colour: 0x828282ff,
             show_minimap: true,
             minimap_scale: 6,
-            minimap_scale_px: 0,
+            minimap_width_px: 0,
+            minimap_height_px: 0,
             colour_mapping,
-            resolution_scaling: true 
+            resolution_scaling: true,
+            render_threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            fog: None,
+            sky_mode: SkyMode::Off,
+            editor_brush: None,
+            minimap_cursor: None
         }
     }
 }
 
+impl RenderContext {
+    /// Returns where the minimap is drawn on a screen of the given size,
+    /// in the bottom right corner.
+    fn minimap_rect(&self, screen_width: u32, screen_height: u32) -> Result<Rect, Error> {
+        let minimap_pos_x = i32::try_from(screen_width)? - i32::try_from(self.minimap_width_px)?-10;
+        let minimap_pos_y = i32::try_from(screen_height)? - i32::try_from(self.minimap_height_px)?-10;
+        Ok(Rect::new(minimap_pos_x, minimap_pos_y, self.minimap_width_px, self.minimap_height_px))
+    }
+
+    /// Sizes the minimap to fit the world of `game_context`.
+    /// The minimap is drawn with the y axis of the world going across the screen.
+    fn update_minimap_size(&mut self, game_context: &GameContext) -> Result<(), Error> {
+        self.minimap_width_px = (self.minimap_scale as usize * game_context.world.height()).try_into()?;
+        self.minimap_height_px = (self.minimap_scale as usize * game_context.world.width()).try_into()?;
+        Ok(())
+    }
+}
+
+/// SDL objects needed to present frames to a window.
+/// Not present when the renderer is running headless.
+struct WindowTarget {
+    // RefCell allows for multiple immutable borrows to be used internally
+    render_texture: RefCell<Texture<'static>>,
+    minimap_texture: RefCell<Texture<'static>>,
+    sdl_canvas: sdl2::render::WindowCanvas,
+    creator: TextureCreator<sdl2::video::WindowContext>
+}
+
 pub struct Renderer {
     pub render_context: RenderContext,
-    sdl_canvas: sdl2::render::WindowCanvas,
-    creator: TextureCreator<sdl2::video::WindowContext>,
+    window: Option<WindowTarget>,
     image_textures: Box<[Box<[u32]>]>,
-    render_texture: RefCell<Texture<'static>>,
-    minimap_texture: RefCell<Texture<'static>>,
-    // RefCell allows for multiple immutable borrows to be used internally
+    sky_texture: Option<Box<[u32]>>,
     render_data: Vec<u32>, minimap_data: Vec<u32>,
+    // Distance to the wall drawn in each pixel, stored column by column.
+    // Used to hide sprites behind walls
+    depth_buffer: Vec<f32>,
+    // Scratch buffer holding the frame column by column while it is raycast
+    column_data: Vec<u32>,
     width: u32, height: u32
 }
 
 impl Renderer {
-    pub fn new(sdl_context: &sdl2::Sdl, title: &'static str, width: u32, height: u32) -> Result<Self, Error> {
+    /// Opens a window to draw `game_context` in, loading the textures it uses.
+    pub fn new(sdl_context: &sdl2::Sdl, title: &'static str, width: u32, height: u32, game_context: &GameContext) -> Result<Self, Error> {
         let video_ss = sdl_context.video()?;
         let window = video_ss.window(title, width, height)
             .position_centered()
@@ -77,14 +148,13 @@ impl Renderer {
             .map_err(|e| e.to_string())?;
         let sdl_canvas = window.into_canvas().build()?;
         let creator = sdl_canvas.texture_creator();
-        let render_context = RenderContext::new();
-
-        let minimap_size: u32 = (render_context.minimap_scale as usize * WORLD_SIZE).try_into()?;
+        let mut render_context = RenderContext::new();
+        render_context.update_minimap_size(game_context)?;
 
         let render_texture = creator.create_texture_target(
             PixelFormatEnum::RGBA8888, width, height)?;
         let minimap_texture = creator.create_texture_target(
-                PixelFormatEnum::RGBA8888, minimap_size, minimap_size)?;
+                PixelFormatEnum::RGBA8888, render_context.minimap_width_px, render_context.minimap_height_px)?;
 
         // Reinterpret the bits of `render_texture` and
         // `minimap_texture` as a `Texture<'static>`
@@ -93,26 +163,58 @@ impl Renderer {
              std::mem::transmute::<_,Texture<'static>>(minimap_texture))
         };
 
-        let image_textures = crate::texture::load_textures()?;
+        let window = WindowTarget {
+            render_texture: RefCell::new(render_texture),
+            minimap_texture: RefCell::new(minimap_texture),
+            sdl_canvas, creator
+        };
+
+        Self::with_target(render_context, Some(window), width, height, &game_context.textures)
+    }
+
+    /// Creates a renderer which draws into an in-memory framebuffer only.
+    /// 
+    /// No window is opened and the SDL video subsystem is never initialised,
+    /// so this can be used on machines without a display.
+    /// The finished frame can be read back with `frame_rgba` or `save_frame_png`.
+    pub fn new_headless(width: u32, height: u32, game_context: &GameContext) -> Result<Self, Error> {
+        if width == 0 || height == 0 {
+            return Err(format!("RENDER - Invalid headless frame size {width}x{height}").into())
+        }
+
+        let mut render_context = RenderContext::new();
+        // There is no window to resize, so the framebuffer is always the output size
+        render_context.resolution_scaling = false;
+        render_context.update_minimap_size(game_context)?;
+
+        Self::with_target(render_context, None, width, height, &game_context.textures)
+    }
+
+    fn with_target(render_context: RenderContext, window: Option<WindowTarget>, width: u32, height: u32, textures: &[String]) -> Result<Self, Error> {
+        let minimap_size = render_context.minimap_width_px * render_context.minimap_height_px;
+        let image_textures = crate::texture::load_textures(textures)?;
 
         Ok(Self {
             render_context,
-            sdl_canvas, creator,
+            window,
             image_textures,
-            render_texture: RefCell::new(render_texture),
-            minimap_texture: RefCell::new(minimap_texture),
+            sky_texture: None,
             width, height,
             render_data: vec![0; (width*height) as usize],
-            minimap_data: vec![0; (minimap_size) as usize]
+            minimap_data: vec![0; (minimap_size) as usize],
+            depth_buffer: Vec::new(),
+            column_data: Vec::new()
         })
     }
 
     pub fn draw(&mut self, game_context: &GameContext) -> Result<(), Error> {
-        if !self.render_context.resolution_scaling { self.update_texture_sizes()? }
-        self.render_context.minimap_scale_px = (self.render_context.minimap_scale as usize * WORLD_SIZE).try_into()?;
+        self.update_texture_sizes(game_context)?;
         self.clear(&TextureTarget::Minimap);
-        self.draw_background();
         self.draw_world(game_context)?;
+        self.draw_sprites(game_context);
+        if let Some(brush) = self.render_context.editor_brush {
+            self.draw_texture_picker(game_context, brush);
+        }
         if self.render_context.show_minimap {
             self.draw_minimap_cells(game_context)?;
             self.draw_player_on_minimap(game_context)?;
@@ -121,53 +223,186 @@ impl Renderer {
         Ok(())
     }
 
+    /// Loads the panoramic sky texture from the `/img/` directory,
+    /// where it is drawn depends on `RenderContext::sky_mode`.
+    pub fn set_sky_texture(&mut self, file_name: &str) -> Result<(), Error> {
+        self.sky_texture = Some(crate::texture::load_sky_texture(file_name)?);
+        Ok(())
+    }
+
+    /// Sets the sky texture and where it is drawn from a map's metadata, `None` turns the sky off.
+    /// Like a wall texture, a sky which can't be loaded is reported rather than stopping the game,
+    /// and the flat ceiling colour is drawn instead.
+    pub fn set_sky(&mut self, sky: Option<&(String, SkyMode)>) {
+        self.render_context.sky_mode = match sky {
+            Some((file_name, mode)) => match self.set_sky_texture(file_name) {
+                Ok(()) => *mode,
+                Err(err) => {
+                    println!("WARN: Could not load sky '{}' - {:?}", file_name, err);
+                    SkyMode::Off
+                }
+            },
+            None => SkyMode::Off
+        };
+    }
+
+    /// Replaces every texture with the ones in `textures`, such as after the map has changed.
+    pub fn load_textures(&mut self, textures: &[String]) -> Result<(), Error> {
+        self.image_textures = crate::texture::load_textures(textures)?;
+        Ok(())
+    }
+
+    /// Reloads the texture at `index` from `file_name`.
+    /// The old texture is kept if the new one can't be loaded.
+    pub fn reload_texture(&mut self, index: usize, file_name: &str) -> Result<(), Error> {
+        let texture = crate::texture::load_texture(file_name)?;
+        if let Some(slot) = self.image_textures.get_mut(index) {
+            *slot = texture;
+        }
+        Ok(())
+    }
+
     fn swap(&mut self) -> Result<(), Error> {
+        // Nothing to present to when running headless,
+        // the frame stays in `render_data`
+        let Some(window) = self.window.as_mut() else { return Ok(()) };
+
         let (actual_width, actual_height) =
             if self.render_context.resolution_scaling {
-                self.sdl_canvas.output_size()?
+                window.sdl_canvas.output_size()?
             } else {
                 (self.width, self.height)
             };
 
-        let mut render_texture = self.render_texture.borrow_mut();
+        let mut render_texture = window.render_texture.borrow_mut();
 
-        render_texture.update(None, self.data_raw(TextureTarget::Render),
+        render_texture.update(None, as_bytes(&self.render_data),
             (self.width*4) as usize)?;
 
-        self.sdl_canvas.copy(&render_texture,None,Rect::new(0,0,actual_width,actual_height))?;
+        window.sdl_canvas.copy(&render_texture,None,Rect::new(0,0,actual_width,actual_height))?;
 
         if self.render_context.show_minimap {
-            let mut minimap_texture = self.minimap_texture.borrow_mut();
+            let mut minimap_texture = window.minimap_texture.borrow_mut();
 
-            minimap_texture.update(None, self.data_raw(TextureTarget::Minimap),
-                (self.render_context.minimap_scale_px*4) as usize)?;
+            minimap_texture.update(None, as_bytes(&self.minimap_data),
+                (self.render_context.minimap_width_px*4) as usize)?;
 
-            let minimap_pos_x = i32::try_from(actual_width)? - i32::try_from(self.render_context.minimap_scale_px)?-10;
-            let minimap_pos_y = i32::try_from(actual_height)? - i32::try_from(self.render_context.minimap_scale_px)?-10;
-            self.sdl_canvas.copy(&minimap_texture, None,
-                Rect::new(minimap_pos_x, minimap_pos_y,
-                    self.render_context.minimap_scale_px, self.render_context.minimap_scale_px)
-            )?;
+            let minimap_rect = self.render_context.minimap_rect(actual_width, actual_height)?;
+            window.sdl_canvas.copy(&minimap_texture, None, minimap_rect)?;
         }
 
-        self.sdl_canvas.present();
+        window.sdl_canvas.present();
 
         Ok(())
     }
 
-    fn update_texture_sizes(&mut self) -> Result<(), Error> {
-        let (new_width, new_height) = self.sdl_canvas.output_size()?;
+    /// Returns the size of the window's drawable area in pixels,
+    /// or the frame size when running headless.
+    pub fn output_size(&self) -> Result<(u32, u32), Error> {
+        match &self.window {
+            Some(window) => Ok(window.sdl_canvas.output_size()?),
+            None => Ok((self.width, self.height))
+        }
+    }
+
+    /// Returns the world cell shown on the minimap at a point on the screen, if there is one.
+    pub fn minimap_cell_at(&self, screen_x: i32, screen_y: i32) -> Option<(usize, usize)> {
+        if !self.render_context.show_minimap { return None }
+
+        let (screen_width, screen_height) = self.output_size().ok()?;
+        let rect = self.render_context.minimap_rect(screen_width, screen_height).ok()?;
+        if !rect.contains_point((screen_x, screen_y)) { return None }
+
+        // The minimap is drawn with the y axis of the world going across the screen
+        let scale = self.render_context.minimap_scale as i32;
+        Some((((screen_y - rect.y()) / scale) as usize, ((screen_x - rect.x()) / scale) as usize))
+    }
+
+    /// Returns which slot of the texture picker is at a point on the screen, if any.
+    /// `slots` is the number of slots in the picker.
+    pub fn picker_slot_at(&self, screen_x: i32, screen_y: i32, slots: usize) -> Option<usize> {
+        let (screen_width, screen_height) = self.output_size().ok()?;
+        if screen_x < 0 || screen_y < 0 || screen_width == 0 || screen_height == 0 { return None }
+
+        // The frame is stretched over the whole window
+        let frame_x = screen_x as u32 * self.width / screen_width;
+        let frame_y = screen_y as u32 * self.height / screen_height;
+
+        (0..slots).find(|&slot| {
+            let (slot_x, slot_y) = self.picker_slot_position(slot);
+            (slot_x..slot_x + PICKER_SLOT_SIZE).contains(&frame_x) && (slot_y..slot_y + PICKER_SLOT_SIZE).contains(&frame_y)
+        })
+    }
+
+    /// Returns the top left corner of a texture picker slot in the frame.
+    /// Slots run along the top of the frame, wrapping onto new rows when they run out of space.
+    fn picker_slot_position(&self, slot: usize) -> (u32, u32) {
+        let stride = PICKER_SLOT_SIZE + PICKER_SLOT_GAP;
+        let per_row = ((self.width.saturating_sub(PICKER_SLOT_GAP)) / stride).max(1) as usize;
+        (
+            PICKER_SLOT_GAP + (slot % per_row) as u32 * stride,
+            PICKER_SLOT_GAP + (slot / per_row) as u32 * stride
+        )
+    }
+
+    /// Draws a slot for each cell value, showing the north face of its cell type,
+    /// with a border around the `selected` one. Slot 0 is the eraser.
+    fn draw_texture_picker(&mut self, game_context: &GameContext, selected: i32) {
+        for slot in 0..=game_context.cell_types.len() {
+            let (slot_x, slot_y) = self.picker_slot_position(slot);
+            let texture = game_context.cell_type(slot as i32)
+                .map(|cell_type| cell_type.texture(Face::North) - 1)
+                .and_then(|texture| usize::try_from(texture).ok())
+                .filter(|&texture| texture < self.image_textures.len());
+
+            let border_colour = if slot as i32 == selected { 0xffffffff } else { 0x000000ff };
+
+            for dx in 0..PICKER_SLOT_SIZE {
+                for dy in 0..PICKER_SLOT_SIZE {
+                    let on_border = dx < 2 || dy < 2 || dx >= PICKER_SLOT_SIZE - 2 || dy >= PICKER_SLOT_SIZE - 2;
+
+                    let colour = if on_border {
+                        border_colour
+                    } else if let Some(texture) = texture {
+                        let texel_x = dx as usize * TEXTURE_WIDTH / PICKER_SLOT_SIZE as usize;
+                        let texel_y = dy as usize * TEXTURE_WIDTH / PICKER_SLOT_SIZE as usize;
+                        self.image_textures[texture][texel_y * TEXTURE_WIDTH + texel_x] | 0xff
+                    } else {
+                        // The eraser is a red cross on the floor colour
+                        if dx == dy || dx + dy == PICKER_SLOT_SIZE - 1 { 0xff0000ff } else { self.render_context.floor_colour }
+                    };
+
+                    self.set_pixel(&TextureTarget::Render, slot_x + dx, slot_y + dy, colour);
+                }
+            }
+        }
+    }
+
+    /// Resizes the textures to match the window, and the minimap to match the world.
+    fn update_texture_sizes(&mut self, game_context: &GameContext) -> Result<(), Error> {
+        let (minimap_width, minimap_height) = (self.render_context.minimap_width_px, self.render_context.minimap_height_px);
+        self.render_context.update_minimap_size(game_context)?;
+        let minimap_resized = minimap_width != self.render_context.minimap_width_px
+            || minimap_height != self.render_context.minimap_height_px;
+
+        let Some(window) = self.window.as_mut() else { return Ok(()) };
 
-        if new_width != self.width || new_height != self.height{
+        // With resolution scaling the frame keeps its size and is stretched to fit the window
+        let (new_width, new_height) =
+            if self.render_context.resolution_scaling {
+                (self.width, self.height)
+            } else {
+                window.sdl_canvas.output_size()?
+            };
+
+        if new_width != self.width || new_height != self.height || minimap_resized {
             self.width = new_width;
             self.height = new_height;
 
-            let minimap_size: u32 = (self.render_context.minimap_scale as usize * WORLD_SIZE).try_into()?;
-
-            let render_texture = self.creator.create_texture_target(
+            let render_texture = window.creator.create_texture_target(
                 PixelFormatEnum::RGBA8888, self.width, self.height)?;
-            let minimap_texture = self.creator.create_texture_target(
-                    PixelFormatEnum::RGBA8888, minimap_size, minimap_size)?;
+            let minimap_texture = window.creator.create_texture_target(
+                    PixelFormatEnum::RGBA8888, self.render_context.minimap_width_px, self.render_context.minimap_height_px)?;
     
             // Reinterpret the bits of `render_texture` and
             // `minimap_texture` as a `Texture<'static>`
@@ -176,45 +411,69 @@ impl Renderer {
                  std::mem::transmute::<_,Texture<'static>>(minimap_texture))
             };
 
-            self.render_texture = RefCell::new(render_texture);
-            self.minimap_texture = RefCell::new(minimap_texture);
+            window.render_texture = RefCell::new(render_texture);
+            window.minimap_texture = RefCell::new(minimap_texture);
                 
-            self.minimap_data = vec![0; (self.render_context.minimap_scale_px.pow(2)) as usize];
+            self.minimap_data = vec![0; (self.render_context.minimap_width_px * self.render_context.minimap_height_px) as usize];
             self.render_data = vec![0; (self.width*self.height) as usize];
         }
 
         Ok(())
     }
 
-    fn data_raw(&self, target: TextureTarget) -> &[u8] {
-        let data = match target {
-            TextureTarget::Render => &self.render_data,
-            TextureTarget::Minimap => &self.minimap_data
-        };
+    /// Returns the last drawn frame as tightly packed RGBA bytes, row by row.
+    /// 
+    /// If the minimap is enabled it is composited into the bottom right
+    /// corner, matching where `swap` places it in the window.
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        let mut frame = self.render_data.clone();
 
-        unsafe {
-            std::slice::from_raw_parts(
-                data.as_ptr() as *const u8,
-                data.len()*4
-            )
+        if self.render_context.show_minimap {
+            let minimap_width = self.render_context.minimap_width_px as i64;
+            let minimap_height = self.render_context.minimap_height_px as i64;
+            let origin_x = self.width as i64 - minimap_width - 10;
+            let origin_y = self.height as i64 - minimap_height - 10;
+
+            for (i, &src) in self.minimap_data.iter().enumerate() {
+                let x = origin_x + i as i64 % minimap_width;
+                let y = origin_y + i as i64 / minimap_width;
+                if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+                    continue
+                }
+                let dst = &mut frame[(y * self.width as i64 + x) as usize];
+                *dst = blend(*dst, src);
+            }
         }
+
+        frame.iter().flat_map(|pixel| pixel.to_be_bytes()).collect()
+    }
+
+    /// Saves the last drawn frame, as returned by `frame_rgba`, to a PNG file.
+    pub fn save_frame_png(&self, path: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        image::save_buffer(
+            path,
+            &self.frame_rgba(),
+            self.width, self.height,
+            image::ColorType::Rgba8
+        )?;
+        Ok(())
     }
 
     fn set_pixel(&mut self, target: &TextureTarget, x: u32, y: u32, colour: u32) {
 
-        let (max_x, max_y) = match target {
-            TextureTarget::Render => (self.width - 1, self.height - 1),
-            TextureTarget::Minimap => (self.render_context.minimap_scale_px - 1, self.render_context.minimap_scale_px - 1)
+        let (target_width, target_height) = match target {
+            TextureTarget::Render => (self.width, self.height),
+            TextureTarget::Minimap => (self.render_context.minimap_width_px, self.render_context.minimap_height_px)
         };
 
-        if x  > max_x || y > max_y {
+        if x >= target_width || y >= target_height {
             //println!("Warning: attempted to write to pixel {}, {}, which is outside of the texture", x, y);
             return
         }
 
         match target {
             TextureTarget::Render => self.render_data[(y * self.width + x) as usize] = colour,
-            TextureTarget::Minimap => self.minimap_data[(y * self.render_context.minimap_scale_px + x) as usize] = colour
+            TextureTarget::Minimap => self.minimap_data[(y * self.render_context.minimap_width_px + x) as usize] = colour
         }
     }
 
@@ -320,23 +579,77 @@ impl Renderer {
         }
     }
 
-    fn draw_background(&mut self) {
-        for x in 0..self.width {
-            for y in 0..(self.height/2) {
-                self.set_pixel(&TextureTarget::Render, x, y, self.render_context.ceil_colour)
+    /// Draws the floor, ceiling and walls.
+    /// 
+    /// Columns are raycast in parallel bands, using `render_threads` threads.
+    /// Each column is drawn by the same function whichever thread it is on,
+    /// so the output does not depend on the number of threads.
+    fn draw_world(&mut self, context: &GameContext) -> Result<(), Error> {
+
+        let width = self.width as usize;
+        let height = self.height as usize;
+
+        // Column-major copy of the frame, so each column is a contiguous
+        // slice which can be handed to a thread on its own.
+        let mut column_data = std::mem::take(&mut self.column_data);
+        column_data.resize(width * height, 0);
+
+        let mut depth_buffer = std::mem::take(&mut self.depth_buffer);
+        depth_buffer.resize(width * height, f32::INFINITY);
+
+        let mut hits = vec![ColumnHit::default(); width];
+
+        let view = FrameView::new(context, &self.render_context,
+            &self.image_textures, self.sky_texture.as_deref(), self.width, self.height);
+
+        // Draws a band of neighbouring columns, starting at `first_column`
+        let draw_band = |first_column: usize, columns: &mut [u32], depths: &mut [f32], hits: &mut [ColumnHit]| {
+            let band = columns.chunks_mut(height).zip(depths.chunks_mut(height)).zip(hits.iter_mut());
+            for (i, ((column, depth), hit)) in band.enumerate() {
+                *hit = view.draw_column((first_column + i) as u32, column, depth);
             }
+        };
+
+        let threads = self.render_context.render_threads.clamp(1, width.max(1));
+
+        if threads == 1 {
+            draw_band(0, &mut column_data, &mut depth_buffer, &mut hits);
+        } else {
+            let band_width = width.div_ceil(threads);
+
+            std::thread::scope(|scope| {
+                let bands = column_data.chunks_mut(band_width * height)
+                    .zip(depth_buffer.chunks_mut(band_width * height))
+                    .zip(hits.chunks_mut(band_width));
+
+                for (band_index, ((band_columns, band_depths), band_hits)) in bands.enumerate() {
+                    let draw_band = &draw_band;
+                    scope.spawn(move || draw_band(band_index * band_width, band_columns, band_depths, band_hits));
+                }
+            });
         }
 
-        for x in 0..self.width {
-            for y in (self.height/2)..self.height {
-                self.set_pixel(&TextureTarget::Render, x, y, self.render_context.floor_colour)
+        // Copy the columns into the row-major frame
+        for (x, column) in column_data.chunks(height).enumerate() {
+            for (y, colour) in column.iter().enumerate() {
+                self.set_pixel(&TextureTarget::Render, x as u32, y as u32, *colour);
             }
         }
+
+        self.column_data = column_data;
+        self.depth_buffer = depth_buffer;
+
+        if self.render_context.show_minimap {
+            self.draw_minimap_rays(context, &hits)?;
+        }
+
+        Ok(())
     }
 
-    fn draw_world(&mut self, context: &GameContext) -> Result<(), Error> {
+    /// Draws a selection of the rays cast in `draw_world` onto the minimap.
+    fn draw_minimap_rays(&mut self, context: &GameContext, hits: &[ColumnHit]) -> Result<(), Error> {
 
-        let minimap_cell_size = self.render_context.minimap_scale_px as usize / WORLD_SIZE;
+        let minimap_cell_size = self.render_context.minimap_scale as usize;
 
         let pos_x = context.player.position.x;
         let pos_y = context.player.position.y;
@@ -344,215 +657,111 @@ impl Renderer {
         let minimap_scaled_pos_x = (pos_y*minimap_cell_size as f32).max(0.0) as u32;
         let minimap_scaled_pos_y = (pos_x*minimap_cell_size as f32).max(0.0) as u32;
 
-        let minimap_ray_interval: u32 = if self.render_context.minimap_scale >= 40 {
+        let minimap_ray_interval: usize = if self.render_context.minimap_scale >= 40 {
             1
         } else {
-            (-0.133*self.render_context.minimap_scale as f32 + 6.33) as u32
+            (-0.133*self.render_context.minimap_scale as f32 + 6.33) as usize
         };
 
-        for column_index in 0..self.width {
-
-            let camera_x: f32 = ((2 * column_index) as f32 / self.width as f32) - 1.0;
+        for hit in hits.iter().step_by(minimap_ray_interval) {
+            self.draw_line(
+                &TextureTarget::Minimap,
 
-            let ray_dir_x = context.player.camera_direction.x + context.player.camera_plane.x * camera_x as f32;
-            let ray_dir_y = context.player.camera_direction.y + context.player.camera_plane.y * camera_x as f32;
+                (hit.ray_intersection.y * minimap_cell_size as f32) as i32,
+                (hit.ray_intersection.x * minimap_cell_size as f32) as i32,
 
-            let mut map_x = pos_x as i32;
-            let mut map_y = pos_y as i32;
+                minimap_scaled_pos_x.try_into()?,
+                minimap_scaled_pos_y.try_into()?,
 
-            let delta_dist_x = if ray_dir_x == 0.0 {f32::MAX} else {(1.0/ray_dir_x).abs()};
-            let delta_dist_y = if ray_dir_y == 0.0 {f32::MAX} else {(1.0/ray_dir_y).abs()};
-
-            let step_x: i32;
-            let step_y: i32;
-
-            let mut side_dist_x: f32;
-            let mut side_dist_y: f32;
+                2,
+                0xFFFFFFFF
+            )?;
+        }
 
-            if ray_dir_x < 0.0 {
-                step_x = -1;
-                side_dist_x = (pos_x - map_x as f32) * delta_dist_x
-            } else {
-                step_x = 1;
-                side_dist_x = ((map_x + 1) as f32 - pos_x) * delta_dist_x;
-            }
-            
-            if ray_dir_y < 0.0 {
-                step_y = -1;
-                side_dist_y = (pos_y - map_y as f32) * delta_dist_y;
-            } else {
-                step_y = 1;
-                side_dist_y = ((map_y + 1) as f32 - pos_y) * delta_dist_y;
-            }
+        Ok(())
+    }
 
-            let mut hit = false;
-            let mut side_facing_x_axis = false;
-
-            while !hit {
-                if side_dist_x < side_dist_y {
-                    side_dist_x += delta_dist_x;
-                    map_x += step_x;
-                    side_facing_x_axis = true;
-                } else {
-                    side_dist_y += delta_dist_y;
-                    map_y += step_y;
-                    side_facing_x_axis = false;
-                }
+    /// Draws every sprite in the world as a camera facing billboard.
+    /// 
+    /// Sprites are drawn from furthest to nearest so closer sprites cover further ones,
+    /// and are clipped against the walls using the depth buffer from `draw_world`.
+    fn draw_sprites(&mut self, context: &GameContext) {
 
-                if map_x < 0 || map_y < 0 {
-                    todo!("Implement proper handling for rays which are out of bounds");
-                }
+        let player = &context.player;
 
-                if context.world.get(map_x as usize).unwrap().get(map_y as usize).unwrap() > &0 {
-                    hit = true;
-                }
-            }
+        // Sort sprites by distance to the player, furthest first
+        let mut order: Vec<(usize, f32)> = context.sprites.iter().enumerate()
+            .map(|(i, sprite)| {
+                let dx = player.position.x - sprite.position.x;
+                let dy = player.position.y - sprite.position.y;
+                (i, dx * dx + dy * dy)
+            })
+            .collect();
+        order.sort_by(|a, b| b.1.total_cmp(&a.1));
 
-            let perpendicular_wall_dist: f32;
+        // Inverse of the camera matrix [plane dir]
+        let inverse_determinant = 1.0 / (player.camera_plane.x * player.camera_direction.y
+            - player.camera_direction.x * player.camera_plane.y);
 
-            if side_facing_x_axis {
-                perpendicular_wall_dist = side_dist_x - delta_dist_x;
-            } else {
-                perpendicular_wall_dist = side_dist_y - delta_dist_y;
-            }
-            
-            let ray_intersection_x: f32;
-            let ray_intersection_y: f32;
+        for (i, _) in order {
+            let sprite = &context.sprites[i];
 
-            // Calculate the exact x, y coordinates the ray intersects with
-            if side_facing_x_axis && step_x == -1 {
-                ray_intersection_x = (map_x+1) as f32;
-                ray_intersection_y = pos_y + perpendicular_wall_dist * ray_dir_y;
-            }
-            else if side_facing_x_axis && step_x == 1 {
-                ray_intersection_x = map_x as f32;
-                ray_intersection_y = pos_y + perpendicular_wall_dist * ray_dir_y;
-            }
-            else if !side_facing_x_axis && step_y == -1 {
-                ray_intersection_x = pos_x + perpendicular_wall_dist * ray_dir_x;
-                ray_intersection_y = (map_y+1) as f32;
-            }
-            else if !side_facing_x_axis && step_y == 1 {
-                ray_intersection_x = pos_x + perpendicular_wall_dist * ray_dir_x;
-                ray_intersection_y = map_y as f32;
-            }
-            // It shouldn't be possible for all of the above to return false,
-            // but just in case that happens, just set the intersection coordinates to the origin of the ray
-            else {
-                ray_intersection_x = pos_x;
-                ray_intersection_y = pos_y;
-            }
+            // Sprite position relative to the camera
+            let relative_x = sprite.position.x - player.position.x;
+            let relative_y = sprite.position.y - player.position.y;
 
-            if column_index % minimap_ray_interval == 0
-                && self.render_context.show_minimap {
+            let transform_x = inverse_determinant * (player.camera_direction.y * relative_x - player.camera_direction.x * relative_y);
+            // Depth of the sprite, equivalent to `perpendicular_wall_dist` for walls
+            let transform_y = inverse_determinant * (-player.camera_plane.y * relative_x + player.camera_plane.x * relative_y);
 
-                self.draw_line(
-                    &TextureTarget::Minimap,
+            // Sprite is behind the camera
+            if transform_y <= 0.0 { continue }
 
-                    (ray_intersection_y * minimap_cell_size as f32) as i32,
-                    (ray_intersection_x * minimap_cell_size as f32) as i32,
+            let screen_x = (self.width as f32 / 2.0) * (1.0 + transform_x / transform_y);
 
-                    minimap_scaled_pos_x.try_into()?,
-                    minimap_scaled_pos_y.try_into()?,
+            // Sprites are a single cell in size, standing on the floor
+            let sprite_size = self.height as f32 / transform_y;
+            let eye_height = player.eye_height() * self.render_context.wall_height as f32;
+            let bottom = player.horizon(self.height) as f32 + eye_height * sprite_size;
+            let top = bottom - sprite_size;
+            let left = screen_x - sprite_size / 2.0;
 
-                    2,
-                    0xFFFFFFFF
-                )?;
+            let draw_start_x = left.max(0.0) as u32;
+            let draw_end_x = (left + sprite_size).min(self.width as f32).max(0.0) as u32;
+            let draw_start_y = top.max(0.0) as u32;
+            let draw_end_y = bottom.min(self.height as f32).max(0.0) as u32;
 
-            }
-            
-            let line_height: u32;
+            let texture = &self.image_textures[sprite.texture as usize - 1];
 
-            if self.render_context.dev_fixed_wall_height {
-                line_height = self.render_context.dev_fixed_wall_height_value;
-            } else {
-                line_height = ((self.height * self.render_context.wall_height) as f32 / perpendicular_wall_dist) as u32;
-            }  
-
-            let draw_start = std::cmp::max(
-                -((line_height / 2) as i32) + (self.height / 2) as i32,
-                0
-            ) as u32;
-            let draw_end = std::cmp::min(
-                (line_height / 2) as i32 + (self.height / 2) as i32,
-                i32::try_from(self.height - 1)?
-            ) as u32;
-
-            let step = TEXTURE_WIDTH as f32 / line_height as f32;
-            let texture_index = context.world[map_x as usize][map_y as usize] as usize - 1;
-            let wall_x;
-
-            if side_facing_x_axis {
-                wall_x = ray_intersection_y - ray_intersection_y.floor()
-            } else {
-                wall_x = ray_intersection_x - ray_intersection_x.floor()
-            }
+            for x in draw_start_x..draw_end_x {
+                let texture_x = (((x as f32 - left) / sprite_size * TEXTURE_WIDTH as f32) as usize).min(TEXTURE_WIDTH - 1);
 
-            let texture_x = i32::try_from(TEXTURE_WIDTH)? - (wall_x * TEXTURE_WIDTH as f32) as i32 - 1;
+                for y in draw_start_y..draw_end_y {
+                    let texture_y = (((y as f32 - top) / sprite_size * TEXTURE_WIDTH as f32) as usize).min(TEXTURE_WIDTH - 1);
 
-            let mut texture_y = (((i32::try_from(draw_start)? - (self.height / 2) as i32) + (line_height / 2) as i32) as f32 * step).max(0.0);
+                    // Sprite is hidden behind a wall in this pixel
+                    if transform_y >= self.depth_buffer[(x * self.height + y) as usize] { continue }
 
-            for y in draw_start..draw_end {
+                    let mut colour = texture[TEXTURE_WIDTH * texture_y + texture_x];
 
-                // Hmm, i don't like this, not sure if it's possible for texture_x to go below 0
-                let mut colour = self.image_textures[texture_index][TEXTURE_WIDTH * texture_y as usize + texture_x as usize];
+                    // Skip transparent texels
+                    if colour & 0xFF == 0 { continue }
 
-                texture_y += step;
+                    if let Some(fog) = &self.render_context.fog {
+                        colour = fog.apply(colour, transform_y);
+                    }
 
-                if !side_facing_x_axis {
-                    // Extract the r,g,b,a components and
-                    // right shift the r,g,b values to half them
-                    let (r, g, b, a) = (
-                        colour >> 24 + 1,
-                        (colour >> 16 & 0xFF) >> 1,
-                        (colour >> 8 & 0xFF) >> 1,
-                        colour & 0xFF
-                    );
-                    
-                    // Shift the values into their correct place
-                    // And combine them
-                    colour = r << 24 | g << 16 | b << 8 | a;
+                    self.render_data[(y * self.width + x) as usize] = colour;
                 }
-
-                self.set_pixel(&TextureTarget::Render, column_index, y, colour)
-
             }
-
-            /*
-            let mut colour = (self.render_context.colour_mapping)(context.world[map_x as usize][map_y as usize]);
-
-            // Half the brightness of the pixels if the side
-            // of the wall they occupy is facing the x axis
-            if !side_facing_x_axis {
-                // Extract the r,g,b,a components and
-                // right shift the r,g,b values to half them
-                let (r, g, b, a) = (
-                    colour >> 24 + 1,
-                    (colour >> 16 & 0xFF) >> 1,
-                    (colour >> 8 & 0xFF) >> 1,
-                    colour & 0xFF
-                );
-                
-                // Shift the values into their correct place
-                // And combine them
-                colour = r << 24 | g << 16 | b << 8 | a;
-            }
-
-            for y in draw_start..draw_end {
-                self.set_pixel(&TextureTarget::Render, column_index, y, colour)
-            }
-            */
-            
         }
-
-        Ok(())
     }
 
     fn draw_minimap_cells(&mut self, game_context: &GameContext) -> Result<(), Error> {
         
-        let minimap_cell_size = self.render_context.minimap_scale_px as usize / WORLD_SIZE;
+        let minimap_cell_size = self.render_context.minimap_scale as usize;
 
-        for y in 0..WORLD_SIZE {
+        for y in 0..game_context.world.width() {
             for (x, cell) in game_context.world[y].iter().enumerate() {
 
                 let cell_colour = (self.render_context.colour_mapping)(*cell);
@@ -576,6 +785,43 @@ impl Renderer {
             }
         }
 
+        // Show the cell borders while editing, so empty cells can be seen
+        if self.render_context.editor_brush.is_some() {
+            let (width_px, height_px) = (self.render_context.minimap_width_px, self.render_context.minimap_height_px);
+            for x in (0..width_px).step_by(minimap_cell_size) {
+                for y in 0..height_px {
+                    self.set_pixel(&TextureTarget::Minimap, x, y, 0x404040ff);
+                }
+            }
+            for y in (0..height_px).step_by(minimap_cell_size) {
+                for x in 0..width_px {
+                    self.set_pixel(&TextureTarget::Minimap, x, y, 0x404040ff);
+                }
+            }
+        }
+
+        // Mark the exit, so the player knows where they are heading
+        if let Some((exit_x, exit_y)) = game_context.exit {
+            let scaled_x = exit_y * minimap_cell_size;
+            let scaled_y = exit_x * minimap_cell_size;
+            for sub_x in scaled_x..scaled_x+minimap_cell_size {
+                for sub_y in scaled_y..scaled_y+minimap_cell_size {
+                    self.set_pixel(&TextureTarget::Minimap, u32::try_from(sub_x)?, u32::try_from(sub_y)?, 0xff8000ff)
+                }
+            }
+        }
+
+        // Outline the cursor in the colour of the cell it would paint
+        if let Some((cursor_x, cursor_y)) = self.render_context.minimap_cursor {
+            let colour = (self.render_context.colour_mapping)(self.render_context.editor_brush.unwrap_or(0));
+            let (left, top) = (cursor_y * minimap_cell_size, cursor_x * minimap_cell_size);
+            for i in 0..minimap_cell_size {
+                for (x, y) in [(left + i, top), (left + i, top + minimap_cell_size - 1), (left, top + i), (left + minimap_cell_size - 1, top + i)] {
+                    self.set_pixel(&TextureTarget::Minimap, u32::try_from(x)?, u32::try_from(y)?, colour);
+                }
+            }
+        }
+
         Ok(())
 
         /*
@@ -607,7 +853,7 @@ impl Renderer {
 
     fn draw_player_on_minimap(&mut self, game_context: &GameContext) -> Result<(), Error> {
 
-        let minimap_cell_size = self.render_context.minimap_scale_px as usize / WORLD_SIZE;
+        let minimap_cell_size = self.render_context.minimap_scale as usize;
 
         let minimap_scaled_pos_x = (game_context.player.position.y*minimap_cell_size as f32).max(0.0) as i32;
         let minimap_scaled_pos_y = (game_context.player.position.x*minimap_cell_size as f32).max(0.0) as i32;
@@ -636,10 +882,114 @@ impl Renderer {
     fn clear(&mut self, target: &TextureTarget) {
         match target {
             TextureTarget::Minimap
-            => {self.minimap_data = vec![0; (self.render_context.minimap_scale_px.pow(2)) as usize]}
+            => {self.minimap_data = vec![0; (self.render_context.minimap_width_px * self.render_context.minimap_height_px) as usize]}
             TextureTarget::Render
             => {self.render_data = vec![0; (self.width*self.height) as usize]}
         }
     }
 
+}
+
+/// Reinterprets a buffer of RGBA u32 pixels as raw bytes.
+fn as_bytes(data: &[u32]) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(
+            data.as_ptr() as *const u8,
+            data.len()*4
+        )
+    }
+}
+
+/// Alpha blends the RGBA colour `src` over `dst`.
+fn blend(dst: u32, src: u32) -> u32 {
+    let alpha = src & 0xFF;
+    match alpha {
+        0 => dst,
+        0xFF => src,
+        _ => {
+            let mix = |shift: u32| {
+                let s = (src >> shift) & 0xFF;
+                let d = (dst >> shift) & 0xFF;
+                (s * alpha + d * (0xFF - alpha)) / 0xFF
+            };
+            mix(24) << 24 | mix(16) << 16 | mix(8) << 8 | 0xFF
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::Grid, map::Map, util::Vector2};
+
+    /// An empty room `size` cells across, walled with texture 1, with the player in the middle facing -x.
+    fn room(size: usize) -> GameContext {
+        let mut world = Grid::new(size, size, 1);
+        for x in 1..size - 1 {
+            for y in 1..size - 1 {
+                world[x][y] = 0;
+            }
+        }
+
+        let centre = size as f32 / 2.0;
+        let map = Map::new(vec!["GRAYWALL.png".to_string()], world, Vector2::new(centre, centre));
+        GameContext::from_map(&map).expect("Test room should be valid")
+    }
+
+    /// Returns the RGBA colour at (`x`, `y`) of a frame returned by `frame_rgba`.
+    fn pixel(frame: &[u8], width: u32, x: u32, y: u32) -> u32 {
+        let i = ((y * width + x) * 4) as usize;
+        u32::from_be_bytes([frame[i], frame[i + 1], frame[i + 2], frame[i + 3]])
+    }
+
+    #[test]
+    fn headless_frame_shows_ceiling_floor_walls_and_minimap() {
+        let context = room(5);
+        let (width, height) = (64, 48);
+        let mut renderer = Renderer::new_headless(width, height, &context).unwrap();
+        renderer.draw(&context).unwrap();
+        let frame = renderer.frame_rgba();
+
+        assert_eq!(frame.len(), (width * height * 4) as usize);
+
+        // The walls are 1.5 cells away, so they cover rows 8 to 40 of each column
+        let column = 4;
+        assert_eq!(pixel(&frame, width, column, 0), renderer.render_context.ceil_colour);
+        assert_eq!(pixel(&frame, width, column, height - 1), renderer.render_context.floor_colour);
+        let is_wall = |colour: u32| {
+            colour != renderer.render_context.ceil_colour
+                && colour != renderer.render_context.floor_colour
+                && colour & 0xff == 0xff
+        };
+        assert!(is_wall(pixel(&frame, width, column, height / 2)));
+
+        // The 30 pixel minimap sits 10 pixels from the bottom right corner,
+        // the corner cell is a wall and the player is in the middle
+        let (minimap_x, minimap_y) = (width - 30 - 10, height - 30 - 10);
+        let wall_colour = (renderer.render_context.colour_mapping)(1);
+        assert_eq!(pixel(&frame, width, minimap_x + 3, minimap_y + 3), wall_colour);
+        assert_eq!(pixel(&frame, width, minimap_x + 15, minimap_y + 15), 0xff0000ff);
+        // Nothing is drawn past the minimap
+        assert!(is_wall(pixel(&frame, width, width - 5, height / 2)));
+    }
+
+    #[test]
+    fn frame_is_the_same_for_any_number_of_threads() {
+        let context = GameContext::new().unwrap();
+        // A prime width, so most thread counts don't divide it
+        let (width, height) = (67, 40);
+
+        let mut renderer = Renderer::new_headless(width, height, &context).unwrap();
+        renderer.render_context.fog = context.metadata.fog;
+        let mut render = |threads: usize| {
+            renderer.render_context.render_threads = threads;
+            renderer.draw(&context).unwrap();
+            renderer.frame_rgba()
+        };
+
+        let single_threaded = render(1);
+        for threads in [2, 3, 4, 7, 16, 66, 67, 68, 500] {
+            assert!(render(threads) == single_threaded, "Frame drawn with {threads} threads differs from one thread");
+        }
+    }
 }
\ No newline at end of file
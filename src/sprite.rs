@@ -0,0 +1,21 @@
+use crate::util::Vector2;
+
+/// A camera facing billboard placed in the world, such as a barrel, pillar or enemy.
+/// 
+/// Sprites are one cell wide and one cell tall and stand on the floor.
+/// Texels with an alpha of 0 are not drawn, so textures can be cut out.
+#[derive(Clone)]
+pub struct Sprite {
+    pub position: Vector2<f32>,
+    /// Texture id, using the same numbering as the world cells
+    pub texture: i32
+}
+
+impl Sprite {
+    pub fn new(x: f32, y: f32, texture: i32) -> Sprite {
+        Sprite {
+            position: Vector2::new(x, y),
+            texture
+        }
+    }
+}
\ No newline at end of file
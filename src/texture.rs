@@ -1,25 +1,33 @@
+use std::path::PathBuf;
+
 use image::{ImageBuffer, Rgba};
 
 use crate::Error;
 
 // TODO: Look into the SDL_Image library to load images instead
 
-// Index directly corresponds to value in map
 pub const TEXTURE_WIDTH: usize = 512;
-pub const N_TEXTURES: usize = 9;
-const TEXTURES_TO_LOAD: [&str; N_TEXTURES] = ["DUNGEONBRICKS.png", "DUNGEONCELL.png", "SPOOKYDOOR.png", "CROSSCUBE.png", "OFFICEDOOR.png", "PIPES.png", "ROUNDBRICKS.png", "LAVAROCKS.png", "GRAYWALL.png"];
 
-/// Loads an image file given a filename into a u32 array.
+/// Size of the panoramic sky texture, which covers a full turn of the camera
+pub const SKY_WIDTH: usize = 2048;
+pub const SKY_HEIGHT: usize = 512;
+
+/// Returns where the image file `file_name` is stored, in the `/img/` directory.
+pub fn texture_path(file_name: &str) -> Result<PathBuf, Error> {
+    Ok(std::env::current_dir()?.join("img").join(file_name))
+}
+
+/// Loads an image file given a filename into a u32 array,
+/// resized to `width` by `height` pixels.
 /// Image files must be stored in the `/img/` directory.
-fn load_resize_png_to_u32_array(file_name: &str) -> Result<Box<[u32]>, Error> {
-    let env = std::env::current_dir().unwrap();
-    let img_path = env.to_str().unwrap().to_owned() + "\\img\\" + file_name;
+fn load_resize_png_to_u32_array(file_name: &str, width: usize, height: usize) -> Result<Box<[u32]>, Error> {
+    let img_path = texture_path(file_name)?;
 
     // Load the image from the given file path
     let img = image::open(img_path)?;
 
     // Resize the image
-    let resized_img = img.resize_exact(TEXTURE_WIDTH.try_into()?, TEXTURE_WIDTH.try_into()?, image::imageops::FilterType::Nearest);
+    let resized_img = img.resize_exact(width.try_into()?, height.try_into()?, image::imageops::FilterType::Nearest);
 
     // Convert the image to a buffer of RGBA format
     let rgba_buffer: ImageBuffer<Rgba<u8>, Vec<u8>> = resized_img.to_rgba8();
@@ -27,7 +35,7 @@ fn load_resize_png_to_u32_array(file_name: &str) -> Result<Box<[u32]>, Error> {
     // Convert the RGBA buffer to an array of u32 in the format RGBA,
     // 1 byte per channel
     // Array is boxed because it is to large to store on the stack.
-    let mut u32_array = vec![0_u32; TEXTURE_WIDTH * TEXTURE_WIDTH].into_boxed_slice();
+    let mut u32_array = vec![0_u32; width * height].into_boxed_slice();
     for (i, pixel) in rgba_buffer.pixels().enumerate() {
         let [r, g, b, a] = pixel.0;
         let rgba_value: u32 = ((r as u32) << 24) | ((g as u32) << 16) | ((b as u32) << 8) | a as u32;
@@ -37,16 +45,16 @@ fn load_resize_png_to_u32_array(file_name: &str) -> Result<Box<[u32]>, Error> {
     Ok(u32_array)
 }
 
-/// Loads all the textures by name from the `TEXTURES_TO_LOAD` array.
-pub fn load_textures() -> Result<Box<[Box<[u32]>]>, Error>  {
+/// Loads all the textures by name from a map's texture list.
+/// Index directly corresponds to value in map, minus one.
+pub fn load_textures(file_names: &[String]) -> Result<Box<[Box<[u32]>]>, Error>  {
     // Array is boxed because it is to large to store on the stack.
     let mut textures = vec![
-        vec![0_u32; TEXTURE_WIDTH * TEXTURE_WIDTH].into_boxed_slice(); N_TEXTURES
+        vec![0_u32; TEXTURE_WIDTH * TEXTURE_WIDTH].into_boxed_slice(); file_names.len()
     ].into_boxed_slice();
 
-    for (i, texture) in TEXTURES_TO_LOAD.iter().enumerate() {
-        let result = load_resize_png_to_u32_array(texture);
-        match result {
+    for (i, texture) in file_names.iter().enumerate() {
+        match load_texture(texture) {
             Ok(u32_array) => {textures[i] = u32_array}
             Err(err) => {
                 // If a texture fails to load, this will result
@@ -57,4 +65,15 @@ pub fn load_textures() -> Result<Box<[Box<[u32]>]>, Error>  {
     }
 
    Ok(textures)
+}
+
+/// Loads a single wall texture, resized to `TEXTURE_WIDTH` square.
+pub fn load_texture(file_name: &str) -> Result<Box<[u32]>, Error> {
+    load_resize_png_to_u32_array(file_name, TEXTURE_WIDTH, TEXTURE_WIDTH)
+}
+
+/// Loads a panoramic sky texture, resized to `SKY_WIDTH` by `SKY_HEIGHT`.
+/// The left and right edges of the image should line up, as the sky wraps around.
+pub fn load_sky_texture(file_name: &str) -> Result<Box<[u32]>, Error> {
+    load_resize_png_to_u32_array(file_name, SKY_WIDTH, SKY_HEIGHT)
 }
\ No newline at end of file
@@ -1,3 +1,4 @@
+#[derive(Clone, Copy)]
 pub struct Vector2<T> {
     pub x: T,
     pub y: T
@@ -7,4 +8,49 @@ impl<T> Vector2<T> {
     pub fn new(x: T, y: T) -> Vector2<T> {
         Vector2 { x, y }
     }
+}
+
+/// Small seeded random number generator (SplitMix64).
+/// 
+/// The same seed always gives the same sequence of numbers on every platform,
+/// which keeps generated levels reproducible.
+#[derive(Clone)]
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0..n`, `n` must be above 0.
+    pub fn range(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Returns a number from 0.0 up to but not including 1.0.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns true with a probability of `p`.
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.next_f32() < p
+    }
+
+    /// Puts `items` in a random order.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.range(i + 1));
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,272 @@
+//! Contains the checks which a map has to pass before it can be played.
+//!
+//! Every problem in the map is reported at once, rather than stopping at the first one,
+//! so a map can be fixed in a single pass.
+
+use std::fmt;
+
+use crate::{
+    door::DoorAxis,
+    game::WorldItem,
+    grid::Grid,
+    map::Map
+};
+
+/// How serious a `Problem` is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The map can't be played
+    Error,
+    /// The map can be played, but probably doesn't do what its author intended
+    Warning
+}
+
+/// Something wrong with a map. Coordinates are world cells, (x, y).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The world and the cell properties are different sizes
+    SizeMismatch { world: (usize, usize), properties: (usize, usize) },
+    /// A cell type face uses a texture id which isn't in the texture list
+    UnknownFaceTexture { cell_type: i32, texture: i32 },
+    UnknownFloorTexture { x: usize, y: usize, texture: i32 },
+    UnknownCeilingTexture { x: usize, y: usize, texture: i32 },
+    UnknownSpriteTexture { sprite: usize, texture: i32 },
+    /// A cell value which isn't a cell type id
+    UnknownCellType { x: usize, y: usize, value: i32 },
+    /// An empty cell on the boundary, which would let the player and rays leave the world
+    ExposedCell { x: usize, y: usize },
+    /// A transparent wall on the boundary, which would let rays leave the world
+    TransparentBoundary { x: usize, y: usize },
+    InvalidWallHeight { x: usize, y: usize, height: f32 },
+    SpawnInWall { x: f32, y: f32 },
+    ExitInWall { x: usize, y: usize },
+    SpriteInWall { sprite: usize, x: f32, y: f32 },
+    DoorOnBoundary { x: usize, y: usize },
+    /// A door in an empty cell, which has nothing to draw the door with
+    DoorWithoutTexture { x: usize, y: usize },
+    /// A door without walls on opposite sides to slide into
+    DoorNotBetweenWalls { x: usize, y: usize },
+    /// Cells which can be walked through but can't be reached from the spawn.
+    /// (`x`, `y`) is one of the cells in the area.
+    UnreachableArea { x: usize, y: usize, size: usize },
+    /// The exit can't be reached from the area around the spawn
+    SealedFromExit { x: usize, y: usize, size: usize }
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::UnreachableArea { .. } => Severity::Warning,
+            _ => Severity::Error
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::SizeMismatch { world, properties } =>
+                write!(f, "World is {}x{} but its cell properties are {}x{}", world.0, world.1, properties.0, properties.1),
+            Problem::UnknownFaceTexture { cell_type, texture } =>
+                write!(f, "Cell type {cell_type} uses texture {texture}, which is not in the texture list"),
+            Problem::UnknownFloorTexture { x, y, texture } =>
+                write!(f, "Floor at ({x}, {y}) uses texture {texture}, which is not in the texture list"),
+            Problem::UnknownCeilingTexture { x, y, texture } =>
+                write!(f, "Ceiling at ({x}, {y}) uses texture {texture}, which is not in the texture list"),
+            Problem::UnknownSpriteTexture { sprite, texture } =>
+                write!(f, "Sprite {sprite} uses texture {texture}, which is not in the texture list"),
+            Problem::UnknownCellType { x, y, value } =>
+                write!(f, "World contains invalid wall {value} at ({x}, {y})"),
+            Problem::ExposedCell { x, y } =>
+                write!(f, "World contains exposed wall at ({x}, {y})"),
+            Problem::TransparentBoundary { x, y } =>
+                write!(f, "World contains transparent wall on the boundrary at ({x}, {y})"),
+            Problem::InvalidWallHeight { x, y, height } =>
+                write!(f, "World contains invalid wall height {height} at ({x}, {y})"),
+            Problem::SpawnInWall { x, y } =>
+                write!(f, "Spawn is outside of the world or inside a wall at ({x}, {y})"),
+            Problem::ExitInWall { x, y } =>
+                write!(f, "Exit is outside of the world or inside a wall at ({x}, {y})"),
+            Problem::SpriteInWall { sprite, x, y } =>
+                write!(f, "Sprite {sprite} is placed outside of the world or inside a wall at ({x}, {y})"),
+            Problem::DoorOnBoundary { x, y } =>
+                write!(f, "Door at ({x}, {y}) is on the boundrary of the world"),
+            Problem::DoorWithoutTexture { x, y } =>
+                write!(f, "Door at ({x}, {y}) is in an empty cell, so it has no texture"),
+            Problem::DoorNotBetweenWalls { x, y } =>
+                write!(f, "Door at ({x}, {y}) is not between two walls"),
+            Problem::UnreachableArea { x, y, size } =>
+                write!(f, "{size} cells around ({x}, {y}) can't be reached from the spawn"),
+            Problem::SealedFromExit { x, y, size } =>
+                write!(f, "Exit can't be reached from the {size} cells around the spawn at ({x}, {y})")
+        }
+    }
+}
+
+/// A problem and the part of the map it was found in.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub item: WorldItem,
+    pub problem: Problem
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.problem.severity() == Severity::Error
+    }
+}
+
+/// Checks every part of `map`, returning all the problems found.
+/// The map can be played if none of them are errors.
+pub fn validate(map: &Map) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut report = |item: WorldItem, problem: Problem| diagnostics.push(Diagnostic { item, problem });
+
+    let world = &map.world;
+    let n_textures = map.textures.len() as i32;
+    let n_cell_types = map.cell_types.len() as i32;
+    let is_texture = |texture: i32| (1..=n_textures).contains(&texture);
+
+    for (i, cell_type) in map.cell_types.iter().enumerate() {
+        let id = i as i32 + 1;
+        for &texture in &cell_type.faces {
+            if !is_texture(texture) {
+                report(WorldItem::CellType(id), Problem::UnknownFaceTexture { cell_type: id, texture });
+            }
+        }
+    }
+
+    for (x, row) in world.rows().enumerate() {
+        for (y, &cell) in row.iter().enumerate() {
+            let item = WorldItem::Cell(x, y);
+
+            if cell > n_cell_types || cell < 0 {
+                report(item, Problem::UnknownCellType { x, y, value: cell });
+                continue
+            }
+            if world.on_boundary(x, y) {
+                if cell == 0 {
+                    report(item, Problem::ExposedCell { x, y });
+                } else if map.cell_types[cell as usize - 1].transparent {
+                    report(item, Problem::TransparentBoundary { x, y });
+                }
+            }
+        }
+    }
+
+    let properties = &map.cell_properties;
+    if world.width() != properties.width() || world.height() != properties.height() {
+        report(WorldItem::World, Problem::SizeMismatch {
+            world: (world.width(), world.height()),
+            properties: (properties.width(), properties.height())
+        });
+    }
+
+    for (x, row) in properties.rows().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            let item = WorldItem::Cell(x, y);
+
+            // 0 is no texture, which is allowed on floors and ceilings
+            if cell.floor_texture != 0 && !is_texture(cell.floor_texture) {
+                report(item, Problem::UnknownFloorTexture { x, y, texture: cell.floor_texture });
+            }
+            if cell.ceiling_texture != 0 && !is_texture(cell.ceiling_texture) {
+                report(item, Problem::UnknownCeilingTexture { x, y, texture: cell.ceiling_texture });
+            }
+            if !(cell.wall_height > 0.0 && cell.wall_height.is_finite()) {
+                report(item, Problem::InvalidWallHeight { x, y, height: cell.wall_height });
+            }
+        }
+    }
+
+    let is_empty = |x: f32, y: f32| x >= 0.0 && y >= 0.0 && world.get(x as usize, y as usize) == Some(&0);
+
+    let spawn = (map.spawn_position.x, map.spawn_position.y);
+    let spawn_is_valid = is_empty(spawn.0, spawn.1);
+    if !spawn_is_valid {
+        report(WorldItem::Spawn, Problem::SpawnInWall { x: spawn.0, y: spawn.1 });
+    }
+
+    let exit_is_valid = match map.exit {
+        Some((x, y)) if world.get(x, y) != Some(&0) => {
+            report(WorldItem::Exit, Problem::ExitInWall { x, y });
+            false
+        }
+        exit => exit.is_some()
+    };
+
+    for (i, sprite) in map.sprites.iter().enumerate() {
+        let (x, y) = (sprite.position.x, sprite.position.y);
+        // Sprites must stand in an empty cell
+        if !is_empty(x, y) {
+            report(WorldItem::Sprite(i), Problem::SpriteInWall { sprite: i, x, y });
+        }
+        if !is_texture(sprite.texture) {
+            report(WorldItem::Sprite(i), Problem::UnknownSpriteTexture { sprite: i, texture: sprite.texture });
+        }
+    }
+
+    for (i, &(x, y)) in map.doors.iter().enumerate() {
+        let item = WorldItem::Door(i);
+
+        if world.get(x, y).is_none() || world.on_boundary(x, y) {
+            report(item, Problem::DoorOnBoundary { x, y });
+            continue
+        }
+        if world[x][y] == 0 {
+            report(item, Problem::DoorWithoutTexture { x, y });
+        }
+        if door_axis(world, x, y).is_none() {
+            report(item, Problem::DoorNotBetweenWalls { x, y });
+        }
+    }
+
+    if spawn_is_valid {
+        // Doors can be opened, so they don't block the way
+        let passable = |x: usize, y: usize, cell: &i32| {
+            *cell == 0 || map.doors.contains(&(x, y))
+                || (*cell > 0 && map.cell_types.get(*cell as usize - 1).is_some_and(|cell_type| !cell_type.solid))
+        };
+
+        let mut reached = Grid::new(world.width(), world.height(), false);
+        let flood = |start: (usize, usize), reached: &mut Grid<bool>| {
+            let area = world.flood_fill(start, passable);
+            for &(x, y) in &area {
+                reached[x][y] = true;
+            }
+            area
+        };
+
+        let spawn_cell = (spawn.0 as usize, spawn.1 as usize);
+        let spawn_area = flood(spawn_cell, &mut reached);
+
+        if let (true, Some(exit)) = (exit_is_valid, map.exit) {
+            if !reached[exit.0][exit.1] {
+                report(WorldItem::Spawn, Problem::SealedFromExit { x: spawn_cell.0, y: spawn_cell.1, size: spawn_area.len() });
+            }
+        }
+
+        for (x, row) in world.rows().enumerate() {
+            for (y, cell) in row.iter().enumerate() {
+                if reached[x][y] || !passable(x, y, cell) { continue }
+
+                let size = flood((x, y), &mut reached).len();
+                report(WorldItem::Cell(x, y), Problem::UnreachableArea { x, y, size });
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Returns the axis a door at (`x`, `y`) opens along, if it has walls on opposite sides to slide into.
+/// The cell must not be on the boundary of the world.
+pub fn door_axis(world: &Grid<i32>, x: usize, y: usize) -> Option<DoorAxis> {
+    if world[x][y - 1] != 0 && world[x][y + 1] != 0 {
+        Some(DoorAxis::X)
+    } else if world[x - 1][y] != 0 && world[x + 1][y] != 0 {
+        Some(DoorAxis::Y)
+    } else {
+        None
+    }
+}
\ No newline at end of file